@@ -0,0 +1,568 @@
+//! Multipurpose clock generator (MCG) and system clock dividers
+//!
+//! The MCG leaves reset in FEI mode, with the FLL multiplying the slow
+//! internal reference up to about 20.97 MHz. `ClockConfig::freeze` walks the
+//! MCG from FEI to the requested mode, sets the SIM_CLKDIV1 dividers within
+//! the device limits and returns the resulting `Clocks`:
+//!
+//! ```ignore
+//! // 120 MHz core from the 50 MHz reference clock of the FRDM-K64F
+//! let clocks = ClockConfig::new(Mode::Pee)
+//!     .external_clock(50.mhz().into())
+//!     .core_frequency(120.mhz().into())
+//!     .freeze(&p.MCG, &p.OSC, &p.SIM)?;
+//! ```
+//!
+//! Boards with a crystal describe it with an `OscConfig` instead:
+//!
+//! ```ignore
+//! let clocks = ClockConfig::new(Mode::Pee)
+//!     .oscillator(OscConfig::crystal(16.mhz().into()).load_capacitance(10))
+//!     .freeze(&p.MCG, &p.OSC, &p.SIM)?;
+//! ```
+
+use crate::pac::{MCG, OSC, SIM};
+use crate::time::Hertz;
+
+pub mod clkout;
+pub mod gate;
+pub mod monitor;
+pub mod trim;
+
+/// Slow internal reference clock
+pub const SLOW_IRC: Hertz = Hertz(32_768);
+/// Fast internal reference clock
+pub const FAST_IRC: Hertz = Hertz(4_000_000);
+
+pub const MAX_CORE: Hertz = Hertz(120_000_000);
+pub const MAX_BUS: Hertz = Hertz(60_000_000);
+pub const MAX_FLEXBUS: Hertz = Hertz(50_000_000);
+pub const MAX_FLASH: Hertz = Hertz(25_000_000);
+
+/// MCG operating modes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// FLL engaged, internal reference
+    Fei,
+    /// FLL engaged, external reference
+    Fee,
+    /// FLL bypassed, internal reference
+    Fbi,
+    /// FLL bypassed, external reference
+    Fbe,
+    /// Bypassed low power, internal reference
+    Blpi,
+    /// Bypassed low power, external reference
+    Blpe,
+    /// PLL bypassed, external reference
+    Pbe,
+    /// PLL engaged, external reference
+    Pee,
+}
+
+impl Mode {
+    fn uses_external(self) -> bool {
+        !matches!(self, Mode::Fei | Mode::Fbi | Mode::Blpi)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockError {
+    /// `freeze` only starts from the reset mode
+    NotInFei,
+    /// The mode needs an external reference
+    NoExternalReference,
+    /// No FLL or PLL setting is within limits for the external frequency
+    ExternalFrequency,
+    /// The requested core frequency cannot be reached in this mode
+    CoreFrequency,
+    /// The selected clock source is not running
+    NotRunning,
+}
+
+/// Low-power oscillator, always running
+pub const LPO: Hertz = Hertz(1_000);
+/// RTC oscillator, once enabled in RTC_CR
+pub const RTC_OSC: Hertz = Hertz(32_768);
+
+/// The RTC oscillator frequency if RTC_CR OSCE is set, the RTC gate is
+/// only opened for the read
+fn rtc_oscillator() -> Option<Hertz> {
+    use self::gate::{Disable, Enable};
+    use crate::pac::RTC;
+
+    let gated = !RTC::is_enabled();
+    RTC::enable();
+    let running = unsafe { (&*RTC::ptr()) }.cr.read().osce().bit();
+    if gated {
+        // Nothing else uses the RTC while its gate was closed
+        unsafe { RTC::disable() };
+    }
+    if running { Some(RTC_OSC) } else { None }
+}
+
+/// Source of the ERCLK32K clock used by the RTC, LPTMR and others
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Erclk32kSource {
+    /// OSC32KCLK, needs OSC0 running with a 32 kHz crystal or clock
+    Osc32k,
+    /// The RTC 32.768 kHz oscillator
+    Rtc,
+    /// The 1 kHz low-power oscillator
+    Lpo,
+}
+
+/// System oscillator (OSC0) setup
+#[derive(Clone, Copy, Debug)]
+pub struct OscConfig {
+    frequency: Hertz,
+    crystal: bool,
+    high_gain: bool,
+    load: u8,
+    oscerclk: bool,
+    oscerclk_in_stop: bool,
+}
+
+impl OscConfig {
+    /// Clock signal driven into EXTAL0, the oscillator is bypassed
+    pub fn external(frequency: Hertz) -> Self {
+        OscConfig {
+            frequency,
+            crystal: false,
+            high_gain: false,
+            load: 0,
+            oscerclk: true,
+            oscerclk_in_stop: false,
+        }
+    }
+
+    /// Crystal or resonator between EXTAL0 and XTAL0, in low-power mode
+    /// without internal load capacitors
+    pub fn crystal(frequency: Hertz) -> Self {
+        OscConfig { crystal: true, ..OscConfig::external(frequency) }
+    }
+
+    /// High-gain mode, for crystals that do not start reliably in low-power
+    /// mode
+    pub fn high_gain(mut self, high_gain: bool) -> Self {
+        self.high_gain = high_gain;
+        self
+    }
+
+    /// Internal load capacitance in pF, rounded down to an even value of
+    /// at most 30 pF
+    pub fn load_capacitance(mut self, pf: u8) -> Self {
+        self.load = pf.min(30) / 2;
+        self
+    }
+
+    /// Whether OSCERCLK is available to peripherals, on by default
+    pub fn oscerclk(mut self, enabled: bool) -> Self {
+        self.oscerclk = enabled;
+        self
+    }
+
+    /// Keeps OSCERCLK and the oscillator running in stop modes
+    pub fn oscerclk_in_stop(mut self, enabled: bool) -> Self {
+        self.oscerclk_in_stop = enabled;
+        self
+    }
+
+    /// Configures OSC_CR and the MCG oscillator selection, waiting for a
+    /// crystal to start when OSCERCLK is enabled
+    fn start(&self, mcg: &MCG, osc: &OSC, range: u8) {
+        osc.cr.write(|w| {
+            w.erclken().bit(self.oscerclk)
+                .erefsten().bit(self.oscerclk_in_stop)
+                .sc2p().bit(self.load & 0b0001 != 0)
+                .sc4p().bit(self.load & 0b0010 != 0)
+                .sc8p().bit(self.load & 0b0100 != 0)
+                .sc16p().bit(self.load & 0b1000 != 0)
+        });
+        mcg.c2.modify(|_, w| unsafe {
+            w.range().bits(range)
+                .hgo().bit(self.high_gain)
+                .erefs().bit(self.crystal)
+        });
+        if self.crystal && self.oscerclk {
+            while !mcg.s.read().oscinit0().bit() {}
+        }
+    }
+}
+
+pub struct ClockConfig {
+    mode: Mode,
+    osc: Option<OscConfig>,
+    fast_irc: bool,
+    core: Option<Hertz>,
+    erclk32k: Erclk32kSource,
+}
+
+impl ClockConfig {
+    pub fn new(mode: Mode) -> Self {
+        ClockConfig { mode, osc: None, fast_irc: false, core: None, erclk32k: Erclk32kSource::Osc32k }
+    }
+
+    /// Frequency of the clock driving EXTAL0, short for
+    /// `oscillator(OscConfig::external(frequency))`
+    pub fn external_clock(self, frequency: Hertz) -> Self {
+        self.oscillator(OscConfig::external(frequency))
+    }
+
+    /// Sets up OSC0, which is also started in the internal reference modes
+    /// to provide OSCERCLK
+    pub fn oscillator(mut self, osc: OscConfig) -> Self {
+        self.osc = Some(osc);
+        self
+    }
+
+    /// Selects the 4 MHz internal reference instead of the 32 kHz one for
+    /// FBI and BLPI
+    pub fn fast_irc(mut self, fast: bool) -> Self {
+        self.fast_irc = fast;
+        self
+    }
+
+    /// Highest core frequency wanted, the FLL or PLL is set as close to it as
+    /// the reference allows. Defaults to the 120 MHz limit.
+    pub fn core_frequency(mut self, frequency: Hertz) -> Self {
+        self.core = Some(frequency);
+        self
+    }
+
+    /// Selects ERCLK32K in SIM_SOPT1. The reset selection, OSC32KCLK, is
+    /// only reported as running when OSC0 runs at 32 kHz, the RTC
+    /// oscillator when it was enabled in RTC_CR before `freeze`.
+    pub fn erclk32k(mut self, source: Erclk32kSource) -> Self {
+        self.erclk32k = source;
+        self
+    }
+
+    /// Switches the MCG to the configured mode, starting from FEI
+    pub fn freeze(self, mcg: &MCG, osc: &OSC, sim: &SIM) -> Result<Clocks, ClockError> {
+        let status = mcg.s.read();
+        if status.clkst().bits() != 0 || !status.irefst().bit() || status.pllst().bit() {
+            return Err(ClockError::NotInFei);
+        }
+        let target = self.core.unwrap_or(MAX_CORE).0.min(MAX_CORE.0);
+        let external = match (self.mode.uses_external(), self.osc) {
+            (true, None) => return Err(ClockError::NoExternalReference),
+            (_, osc) => osc.map(|osc| osc.frequency),
+        };
+        let range = match self.osc {
+            Some(osc) => oscillator_range(osc.frequency, osc.crystal)?,
+            None => 0,
+        };
+        let irc = if self.fast_irc { FAST_IRC } else { SLOW_IRC };
+        let erclk32k = match (self.erclk32k, self.osc) {
+            (Erclk32kSource::Osc32k, Some(osc)) if range == 0 => Some(osc.frequency),
+            (Erclk32kSource::Osc32k, _) => None,
+            (Erclk32kSource::Rtc, _) => rtc_oscillator(),
+            (Erclk32kSource::Lpo, _) => Some(LPO),
+        };
+
+        let mut plan = Plan { frdiv: 0, fll: None, pll: None };
+        if let Some(external) = external {
+            let (frdiv, fll_reference, in_range) = fll_reference(external)?;
+            plan.frdiv = frdiv;
+            if self.mode == Mode::Fee {
+                if !in_range {
+                    return Err(ClockError::ExternalFrequency);
+                }
+                plan.fll = Some(fll_setting(fll_reference, target)?);
+            }
+            if self.mode == Mode::Pbe || self.mode == Mode::Pee {
+                plan.pll = Some(pll_setting(external, target)?);
+            }
+        }
+        if self.mode == Mode::Fei {
+            plan.fll = Some(fll_setting(SLOW_IRC, target)?);
+        }
+
+        let mcgout = match self.mode {
+            Mode::Fei | Mode::Fee => plan.fll.unwrap().output,
+            Mode::Fbi | Mode::Blpi => irc,
+            Mode::Fbe | Mode::Blpe | Mode::Pbe => external.unwrap(),
+            Mode::Pee => plan.pll.unwrap().output,
+        };
+        let dividers = Dividers::new(mcgout, target)?;
+        // Slower intermediate modes stay within limits with the final dividers
+        sim.clkdiv1.write(|w| unsafe {
+            w.outdiv1().bits(dividers.core - 1)
+                .outdiv2().bits(dividers.bus - 1)
+                .outdiv3().bits(dividers.flexbus - 1)
+                .outdiv4().bits(dividers.flash - 1)
+        });
+
+        if let Some(config) = self.osc {
+            config.start(mcg, osc, range);
+        }
+        let osc32ksel = match self.erclk32k {
+            Erclk32kSource::Osc32k => 0b00,
+            Erclk32kSource::Rtc => 0b10,
+            Erclk32kSource::Lpo => 0b11,
+        };
+        sim.sopt1.modify(|_, w| unsafe { w.osc32ksel().bits(osc32ksel) });
+        let crystal = self.osc.is_some_and(|osc| osc.crystal);
+
+        match self.mode {
+            Mode::Fei => set_fll(mcg, plan.fll.unwrap()),
+            Mode::Fee => {
+                set_fll(mcg, plan.fll.unwrap());
+                select_external(mcg, plan.frdiv, crystal, 0b00);
+            }
+            Mode::Fbi | Mode::Blpi => {
+                mcg.c2.modify(|_, w| w.ircs().bit(self.fast_irc));
+                mcg.c1.modify(|_, w| unsafe { w.clks().bits(0b01).irefs().set_bit().irclken().set_bit() });
+                while mcg.s.read().clkst().bits() != 0b01 {}
+                if self.mode == Mode::Blpi {
+                    mcg.c2.modify(|_, w| w.lp().set_bit());
+                }
+            }
+            Mode::Fbe | Mode::Blpe | Mode::Pbe | Mode::Pee => {
+                select_external(mcg, plan.frdiv, crystal, 0b10);
+                if self.mode == Mode::Blpe {
+                    mcg.c2.modify(|_, w| w.lp().set_bit());
+                }
+                if let Some(pll) = plan.pll {
+                    mcg.c5.write(|w| unsafe { w.prdiv0().bits(pll.prdiv - 1) });
+                    mcg.c6.modify(|_, w| unsafe { w.vdiv0().bits(pll.vdiv - 24).plls().set_bit() });
+                    while !mcg.s.read().pllst().bit() {}
+                    while !mcg.s.read().lock0().bit() {}
+                }
+                if self.mode == Mode::Pee {
+                    mcg.c1.modify(|_, w| unsafe { w.clks().bits(0b00) });
+                    while mcg.s.read().clkst().bits() != 0b11 {}
+                }
+            }
+        }
+
+        Ok(Clocks {
+            mode: self.mode,
+            mcgout,
+            core: Hertz(mcgout.0 / dividers.core as u32),
+            bus: Hertz(mcgout.0 / dividers.bus as u32),
+            flexbus: Hertz(mcgout.0 / dividers.flexbus as u32),
+            flash: Hertz(mcgout.0 / dividers.flash as u32),
+            oscer: self.osc.filter(|osc| osc.oscerclk).map(|osc| osc.frequency),
+            mcgir: match self.mode {
+                Mode::Fbi | Mode::Blpi => Some(irc),
+                _ => None,
+            },
+            erclk32k,
+        })
+    }
+}
+
+/// Frozen clock frequencies
+#[derive(Clone, Copy, Debug)]
+pub struct Clocks {
+    mode: Mode,
+    mcgout: Hertz,
+    core: Hertz,
+    bus: Hertz,
+    flexbus: Hertz,
+    flash: Hertz,
+    oscer: Option<Hertz>,
+    mcgir: Option<Hertz>,
+    erclk32k: Option<Hertz>,
+}
+
+impl Clocks {
+    /// Clocks out of reset, FEI with the flash clock divided by two
+    pub fn reset() -> Self {
+        let fei = Hertz(SLOW_IRC.0 * 640);
+        Clocks {
+            mode: Mode::Fei,
+            mcgout: fei,
+            core: fei,
+            bus: fei,
+            flexbus: fei,
+            flash: Hertz(fei.0 / 2),
+            oscer: None,
+            mcgir: None,
+            erclk32k: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// MCGOUTCLK, before the SIM dividers
+    pub fn mcgout(&self) -> Hertz {
+        self.mcgout
+    }
+
+    /// Core and system clock
+    pub fn core(&self) -> Hertz {
+        self.core
+    }
+
+    pub fn bus(&self) -> Hertz {
+        self.bus
+    }
+
+    pub fn flexbus(&self) -> Hertz {
+        self.flexbus
+    }
+
+    pub fn flash(&self) -> Hertz {
+        self.flash
+    }
+
+    /// OSCERCLK, when the oscillator was configured with it enabled
+    pub fn oscer(&self) -> Option<Hertz> {
+        self.oscer
+    }
+
+    /// MCGIRCLK, enabled in the internal reference bypass modes
+    pub fn mcgir(&self) -> Option<Hertz> {
+        self.mcgir
+    }
+
+    /// ERCLK32K, when its selected source is running
+    pub fn erclk32k(&self) -> Option<Hertz> {
+        self.erclk32k
+    }
+}
+
+struct Plan {
+    frdiv: u8,
+    fll: Option<FllSetting>,
+    pll: Option<PllSetting>,
+}
+
+#[derive(Clone, Copy)]
+struct FllSetting {
+    drs: u8,
+    dmx32: bool,
+    output: Hertz,
+}
+
+#[derive(Clone, Copy)]
+struct PllSetting {
+    prdiv: u8,
+    vdiv: u8,
+    output: Hertz,
+}
+
+struct Dividers {
+    core: u8,
+    bus: u8,
+    flexbus: u8,
+    flash: u8,
+}
+
+impl Dividers {
+    /// Smallest dividers within the limits, the peripheral clocks being
+    /// integer fractions of the core clock
+    fn new(mcgout: Hertz, core: u32) -> Result<Self, ClockError> {
+        let divider = |base: u8, limit: u32| {
+            (1..=16u8)
+                .map(|factor| base.saturating_mul(factor))
+                .take_while(|divider| *divider <= 16)
+                .find(|divider| mcgout.0 / *divider as u32 <= limit)
+                .ok_or(ClockError::CoreFrequency)
+        };
+        let core = divider(1, core)?;
+        Ok(Dividers {
+            core,
+            bus: divider(core, MAX_BUS.0)?,
+            flexbus: divider(core, MAX_FLEXBUS.0)?,
+            flash: divider(core, MAX_FLASH.0)?,
+        })
+    }
+}
+
+/// MCG_C2 RANGE for `frequency`, crystals only go up to 32 MHz
+fn oscillator_range(frequency: Hertz, crystal: bool) -> Result<u8, ClockError> {
+    match frequency.0 {
+        32_000..=40_000 => Ok(0),
+        3_000_000..=8_000_000 => Ok(1),
+        8_000_001..=32_000_000 => Ok(2),
+        32_000_001..=50_000_000 if !crystal => Ok(2),
+        _ => Err(ClockError::ExternalFrequency),
+    }
+}
+
+/// FRDIV setting bringing `external` closest to the 32.768 kHz FLL
+/// reference, and whether it is within the 31.25 kHz to 39.0625 kHz the
+/// FLL accepts
+fn fll_reference(external: Hertz) -> Result<(u8, Hertz, bool), ClockError> {
+    let range = oscillator_range(external, false)?;
+    let dividers: [u32; 8] = if range == 0 {
+        [1, 2, 4, 8, 16, 32, 64, 128]
+    } else {
+        [32, 64, 128, 256, 512, 1024, 1280, 1536]
+    };
+    let frdiv = (0..dividers.len())
+        .min_by_key(|frdiv| (external.0 / dividers[*frdiv]).abs_diff(32_768))
+        .unwrap();
+    let reference = external.0 / dividers[frdiv];
+    Ok((frdiv as u8, Hertz(reference), (31_250..=39_062).contains(&reference)))
+}
+
+/// Highest FLL output not above `target`, or the lowest one when they all
+/// are and the core divider has to bring it down
+fn fll_setting(reference: Hertz, target: u32) -> Result<FllSetting, ClockError> {
+    const FACTORS: [(u8, bool, u32); 8] = [
+        (0, false, 640), (0, true, 732), (1, false, 1280), (1, true, 1464),
+        (2, false, 1920), (2, true, 2197), (3, false, 2560), (3, true, 2929),
+    ];
+    let setting = |(drs, dmx32, factor): &(u8, bool, u32)| FllSetting {
+        drs: *drs,
+        dmx32: *dmx32,
+        output: Hertz(reference.0 * factor),
+    };
+    let lowest = setting(&FACTORS[0]);
+    let highest = FACTORS.iter()
+        .rev()
+        .map(setting)
+        // The fine tuned ranges only apply to a 32.768 kHz reference
+        .filter(|setting| !setting.dmx32 || reference.0 == 32_768)
+        .find(|setting| setting.output.0 <= target);
+    Ok(highest.unwrap_or(lowest))
+}
+
+/// PLL setting with a 2 to 4 MHz reference and the output closest to
+/// `target` without exceeding it. Targets below the 48 MHz PLL minimum are
+/// left to the core divider.
+fn pll_setting(external: Hertz, target: u32) -> Result<PllSetting, ClockError> {
+    let target = target.max(48_000_000);
+    let mut best: Option<PllSetting> = None;
+    for prdiv in 1..=25u8 {
+        let reference = external.0 / prdiv as u32;
+        if !(2_000_000..=4_000_000).contains(&reference) {
+            continue;
+        }
+        for vdiv in 24..=55u8 {
+            let output = reference * vdiv as u32;
+            if !(48_000_000..=120_000_000).contains(&output) || output > target {
+                continue;
+            }
+            if best.is_none_or(|best| output > best.output.0) {
+                best = Some(PllSetting { prdiv, vdiv, output: Hertz(output) });
+            }
+        }
+    }
+    best.ok_or(ClockError::ExternalFrequency)
+}
+
+fn set_fll(mcg: &MCG, fll: FllSetting) {
+    mcg.c4.modify(|_, w| unsafe { w.drst_drs().bits(fll.drs).dmx32().bit(fll.dmx32) });
+}
+
+/// Moves the FLL reference to the external clock and selects `clks` as the
+/// MCGOUTCLK source, the oscillator is already configured
+fn select_external(mcg: &MCG, frdiv: u8, crystal: bool, clks: u8) {
+    mcg.c1.modify(|_, w| unsafe {
+        w.clks().bits(clks).frdiv().bits(frdiv).irefs().clear_bit()
+    });
+    if crystal {
+        while !mcg.s.read().oscinit0().bit() {}
+    }
+    while mcg.s.read().irefst().bit() {}
+    let clkst = if clks == 0b10 { 0b10 } else { 0b00 };
+    while mcg.s.read().clkst().bits() != clkst {}
+}
@@ -0,0 +1,77 @@
+//! CLKOUT pin output, for feeding external chips or checking the clock tree
+//! with a scope
+
+use crate::gpio::portc::PC3;
+use crate::gpio::{Alternative, AF5};
+use crate::pac::SIM;
+use crate::time::Hertz;
+use super::{rtc_oscillator, ClockError, Clocks, LPO};
+
+/// Internal 48 MHz reference
+pub const IRC48M: Hertz = Hertz(48_000_000);
+
+/// Clocks that can be routed to CLKOUT with SIM_SOPT2 CLKOUTSEL
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockOutSource {
+    FlexBus,
+    Flash,
+    Lpo,
+    McgIr,
+    /// The RTC 32.768 kHz oscillator, which has to be enabled in RTC_CR
+    Rtc,
+    OscEr,
+    /// The 48 MHz internal reference, which runs while enabled by the USB
+    /// module or the MCG
+    Irc48m,
+}
+
+impl ClockOutSource {
+    fn clkoutsel(self) -> u8 {
+        match self {
+            ClockOutSource::FlexBus => 0b000,
+            ClockOutSource::Flash => 0b010,
+            ClockOutSource::Lpo => 0b011,
+            ClockOutSource::McgIr => 0b100,
+            ClockOutSource::Rtc => 0b101,
+            ClockOutSource::OscEr => 0b110,
+            ClockOutSource::Irc48m => 0b111,
+        }
+    }
+
+    fn frequency(self, clocks: &Clocks) -> Option<Hertz> {
+        match self {
+            ClockOutSource::FlexBus => Some(clocks.flexbus()),
+            ClockOutSource::Flash => Some(clocks.flash()),
+            ClockOutSource::Lpo => Some(LPO),
+            ClockOutSource::McgIr => clocks.mcgir(),
+            ClockOutSource::Rtc => rtc_oscillator(),
+            ClockOutSource::OscEr => clocks.oscer(),
+            ClockOutSource::Irc48m => Some(IRC48M),
+        }
+    }
+}
+
+/// A clock driven out on PTC3
+pub struct ClockOut {
+    pin: PC3<Alternative<AF5>>,
+    frequency: Hertz,
+}
+
+impl ClockOut {
+    /// Routes `source` to the pin, failing when `clocks` has it disabled
+    pub fn new(pin: PC3<Alternative<AF5>>, source: ClockOutSource, clocks: &Clocks, sim: &SIM) -> Result<Self, ClockError> {
+        let frequency = source.frequency(clocks).ok_or(ClockError::NotRunning)?;
+        sim.sopt2.modify(|_, w| unsafe { w.clkoutsel().bits(source.clkoutsel()) });
+        Ok(ClockOut { pin, frequency })
+    }
+
+    /// Frequency of the output
+    pub fn frequency(&self) -> Hertz {
+        self.frequency
+    }
+
+    /// Gives the pin back, the clock keeps being selected in SIM_SOPT2
+    pub fn release(self) -> PC3<Alternative<AF5>> {
+        self.pin
+    }
+}
@@ -0,0 +1,138 @@
+//! Peripheral clock gates in SIM_SCGC1 to SIM_SCGC7
+//!
+//! Registers of a gated peripheral cannot be accessed, drivers enable the
+//! gate when constructed and disable it again when released.
+
+use cortex_m::interrupt;
+use crate::pac::*;
+
+/// Peripherals with a clock gate
+pub trait Enable {
+    /// Ungates the peripheral clock
+    fn enable();
+
+    fn is_enabled() -> bool;
+}
+
+pub trait Disable: Enable {
+    /// Gates the peripheral clock
+    ///
+    /// # Safety
+    ///
+    /// Any access to the peripheral registers bus-faults afterwards, so no
+    /// driver or other code may still be using the peripheral.
+    unsafe fn disable();
+}
+
+macro_rules! gates {
+    ($($scgc:ident => [$($PER:ident: $field:ident,)+],)+) => {
+        $(
+            $(
+                impl Enable for $PER {
+                    fn enable() {
+                        interrupt::free(|_| {
+                            unsafe { (&*SIM::ptr()) }.$scgc.modify(|_, w| w.$field().set_bit())
+                        })
+                    }
+
+                    fn is_enabled() -> bool {
+                        unsafe { (&*SIM::ptr()) }.$scgc.read().$field().bit()
+                    }
+                }
+
+                impl Disable for $PER {
+                    unsafe fn disable() {
+                        interrupt::free(|_| {
+                            unsafe { (&*SIM::ptr()) }.$scgc.modify(|_, w| w.$field().clear_bit())
+                        })
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+// Peripherals that appear in two registers (FTM2, DAC0, RNG) are gated by
+// either bit, only one of them is used here
+gates! {
+    scgc1 => [
+        I2C2: i2c2,
+        UART4: uart4,
+        UART5: uart5,
+    ],
+    scgc2 => [
+        ENET: enet,
+        DAC0: dac0,
+        DAC1: dac1,
+    ],
+    scgc3 => [
+        SPI2: spi2,
+        SDHC: sdhc,
+        FTM3: ftm3,
+        ADC1: adc1,
+    ],
+    scgc4 => [
+        EWM: ewm,
+        CMT: cmt,
+        I2C0: i2c0,
+        I2C1: i2c1,
+        UART0: uart0,
+        UART1: uart1,
+        UART2: uart2,
+        UART3: uart3,
+        USB0: usbotg,
+        VREF: vref,
+    ],
+    scgc5 => [
+        LPTMR0: lptmr,
+        PORTA: porta,
+        PORTB: portb,
+        PORTC: portc,
+        PORTD: portd,
+        PORTE: porte,
+    ],
+    scgc6 => [
+        FTFE: ftf,
+        DMAMUX: dmamux,
+        CAN0: flexcan0,
+        RNG: rnga,
+        SPI0: spi0,
+        SPI1: spi1,
+        I2S0: i2s,
+        CRC: crc,
+        USBDCD: usbdcd,
+        PDB0: pdb,
+        PIT: pit,
+        FTM0: ftm0,
+        FTM1: ftm1,
+        FTM2: ftm2,
+        ADC0: adc0,
+        RTC: rtc,
+    ],
+    scgc7 => [
+        FB: flexbus,
+        DMA: dma,
+        MPU: mpu,
+    ],
+}
+
+// The three comparators share one gate, which is left on once enabled
+macro_rules! shared_gates {
+    ($scgc:ident, $field:ident => [$($PER:ident,)+]) => {
+        $(
+            impl Enable for $PER {
+                fn enable() {
+                    interrupt::free(|_| {
+                        unsafe { (&*SIM::ptr()) }.$scgc.modify(|_, w| w.$field().set_bit())
+                    })
+                }
+
+                fn is_enabled() -> bool {
+                    unsafe { (&*SIM::ptr()) }.$scgc.read().$field().bit()
+                }
+            }
+        )+
+    }
+}
+
+shared_gates!(scgc4, cmp => [CMP0, CMP1, CMP2,]);
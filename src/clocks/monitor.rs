@@ -0,0 +1,167 @@
+//! MCG clock monitors and recovery from a failed external reference
+//!
+//! Each monitor either resets the MCU or raises the MCG interrupt, whose
+//! handler can check `status` and call `recover`:
+//!
+//! ```ignore
+//! monitor::enable_osc0(&mcg, &clocks, LossAction::Interrupt)?;
+//!
+//! #[interrupt]
+//! fn MCG() {
+//!     let status = monitor::status(&mcg);
+//!     if status.osc0_lost() || status.pll_lock_lost() {
+//!         let clocks = monitor::recover(&mcg, &sim, &clocks);
+//!         // reconfigure the drivers for `clocks`
+//!     }
+//! }
+//! ```
+
+use crate::pac::{MCG, SIM};
+use crate::time::Hertz;
+use super::{set_fll, ClockError, Clocks, Dividers, FllSetting, Mode, MAX_CORE, SLOW_IRC};
+
+/// What happens when a monitored clock is lost
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LossAction {
+    /// Raises the MCG interrupt
+    Interrupt,
+    /// Resets the MCU, the cause shows up in the RCM
+    Reset,
+}
+
+/// Monitor flags, set until cleared
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MonitorStatus {
+    osc0_lost: bool,
+    rtc_lost: bool,
+    pll_lock_lost: bool,
+}
+
+impl MonitorStatus {
+    /// Loss of the OSC0 external reference (LOCS0)
+    pub fn osc0_lost(&self) -> bool {
+        self.osc0_lost
+    }
+
+    /// Loss of the RTC 32 kHz oscillator (LOCS1)
+    pub fn rtc_lost(&self) -> bool {
+        self.rtc_lost
+    }
+
+    /// Loss of PLL lock (LOLS0)
+    pub fn pll_lock_lost(&self) -> bool {
+        self.pll_lock_lost
+    }
+}
+
+/// Monitors the external reference, only possible in the modes using it
+pub fn enable_osc0(mcg: &MCG, clocks: &Clocks, action: LossAction) -> Result<(), ClockError> {
+    match clocks.mode() {
+        Mode::Fei | Mode::Fbi | Mode::Blpi => return Err(ClockError::NotRunning),
+        _ => {}
+    }
+    mcg.c2.modify(|_, w| w.locre0().bit(action == LossAction::Reset));
+    mcg.c6.modify(|_, w| w.cme0().set_bit());
+    Ok(())
+}
+
+/// Has to be called before leaving the external reference modes
+pub fn disable_osc0(mcg: &MCG) {
+    mcg.c6.modify(|_, w| w.cme0().clear_bit());
+}
+
+/// Monitors the RTC oscillator, which has to be running
+pub fn enable_rtc(mcg: &MCG, action: LossAction) {
+    // LOCS1 is cleared by writing one, keep it
+    mcg.c8.modify(|_, w| {
+        w.locre1().bit(action == LossAction::Reset)
+            .cme1().set_bit()
+            .locs1().clear_bit()
+    });
+}
+
+pub fn disable_rtc(mcg: &MCG) {
+    mcg.c8.modify(|_, w| w.cme1().clear_bit().locs1().clear_bit());
+}
+
+/// Watches the PLL for loss of lock, only possible while it is running
+pub fn enable_pll(mcg: &MCG, clocks: &Clocks, action: LossAction) -> Result<(), ClockError> {
+    match clocks.mode() {
+        Mode::Pbe | Mode::Pee => {}
+        _ => return Err(ClockError::NotRunning),
+    }
+    mcg.c8.modify(|_, w| w.lolre().bit(action == LossAction::Reset).locs1().clear_bit());
+    mcg.c6.modify(|_, w| w.lolie0().set_bit());
+    Ok(())
+}
+
+pub fn disable_pll(mcg: &MCG) {
+    mcg.c6.modify(|_, w| w.lolie0().clear_bit());
+    mcg.c8.modify(|_, w| w.lolre().clear_bit().locs1().clear_bit());
+}
+
+pub fn status(mcg: &MCG) -> MonitorStatus {
+    MonitorStatus {
+        osc0_lost: mcg.sc.read().locs0().bit(),
+        rtc_lost: mcg.c8.read().locs1().bit(),
+        pll_lock_lost: mcg.s.read().lols0().bit(),
+    }
+}
+
+/// Clears the flags set in `status`
+pub fn clear(mcg: &MCG, status: &MonitorStatus) {
+    if status.osc0_lost {
+        // ATMF is cleared by writing one as well, keep it
+        mcg.sc.modify(|_, w| w.locs0().set_bit().atmf().clear_bit());
+    }
+    if status.rtc_lost {
+        mcg.c8.modify(|_, w| w.locs1().set_bit());
+    }
+    if status.pll_lock_lost {
+        mcg.s.write(|w| w.lols0().set_bit());
+    }
+}
+
+/// Falls back to FEI at about 20.97 MHz, disabling the OSC0 and PLL
+/// monitors, and returns the clocks derived from `clocks` for the new mode
+///
+/// The MCG is left in FEI, so `ClockConfig::freeze` can be retried once
+/// the external reference is back.
+pub fn recover(mcg: &MCG, sim: &SIM, clocks: &Clocks) -> Clocks {
+    disable_osc0(mcg);
+    disable_pll(mcg);
+    // The external reference may be gone, move to the slow internal one
+    // before anything else
+    mcg.c2.modify(|_, w| w.lp().clear_bit());
+    mcg.c1.modify(|_, w| unsafe { w.clks().bits(0b01).irefs().set_bit() });
+    while !mcg.s.read().irefst().bit() {}
+    while mcg.s.read().clkst().bits() != 0b01 {}
+    mcg.c6.modify(|_, w| w.plls().clear_bit());
+    while mcg.s.read().pllst().bit() {}
+
+    let fei = Hertz(SLOW_IRC.0 * 640);
+    set_fll(mcg, FllSetting { drs: 0, dmx32: false, output: fei });
+    let dividers = Dividers::new(fei, MAX_CORE.0).unwrap();
+    sim.clkdiv1.write(|w| unsafe {
+        w.outdiv1().bits(dividers.core - 1)
+            .outdiv2().bits(dividers.bus - 1)
+            .outdiv3().bits(dividers.flexbus - 1)
+            .outdiv4().bits(dividers.flash - 1)
+    });
+    mcg.c1.modify(|_, w| unsafe { w.clks().bits(0b00) });
+    while mcg.s.read().clkst().bits() != 0b00 {}
+
+    let osc_lost = status(mcg).osc0_lost;
+    let osc32k_selected = sim.sopt1.read().osc32ksel().bits() == 0b00;
+    Clocks {
+        mode: Mode::Fei,
+        mcgout: fei,
+        core: Hertz(fei.0 / dividers.core as u32),
+        bus: Hertz(fei.0 / dividers.bus as u32),
+        flexbus: Hertz(fei.0 / dividers.flexbus as u32),
+        flash: Hertz(fei.0 / dividers.flash as u32),
+        oscer: if osc_lost { None } else { clocks.oscer },
+        mcgir: None,
+        erclk32k: if osc_lost && osc32k_selected { None } else { clocks.erclk32k },
+    }
+}
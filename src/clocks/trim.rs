@@ -0,0 +1,163 @@
+//! Internal reference clock trimming
+//!
+//! The IRCs leave reset with the factory trim, which can be replaced with
+//! one measured by the auto trim machine (ATM) against an external
+//! reference. The result can be packed with `to_bits` and reapplied on
+//! later boots without the reference. Storing it, e.g. in flash, is left to
+//! the caller:
+//!
+//! ```ignore
+//! let trim = trim::auto_trim(&mcg, &clocks, Irc::Slow)?;
+//! store(trim.to_bits());
+//! // on a later boot
+//! if let Some(trim) = IrcTrim::from_bits(load()) {
+//!     trim.apply(&mcg);
+//! }
+//! ```
+
+use crate::pac::MCG;
+use super::{Clocks, Mode, FAST_IRC, SLOW_IRC};
+
+/// Top two bits of a packed trim, so erased (0xFFFF) or zeroed storage is
+/// not taken for one
+const MARKER: u16 = 0b01 << 14;
+const MARKER_MASK: u16 = 0b11 << 14;
+
+/// Internal reference clocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Irc {
+    /// The 32 kHz reference
+    Slow,
+    /// The 4 MHz reference
+    Fast,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrimError {
+    /// The MCG runs from an internal reference
+    Mode,
+    /// The bus clock is outside the 8 to 16 MHz the ATM works with
+    BusFrequency,
+    /// The ATM could not reach the compare value
+    Failed,
+}
+
+/// Trim values of both internal references
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IrcTrim {
+    /// SCTRIM, higher values lower the frequency
+    pub slow: u8,
+    /// SCFTRIM
+    pub slow_fine: bool,
+    /// FCTRIM, four bits
+    pub fast: u8,
+    /// FCFTRIM
+    pub fast_fine: bool,
+}
+
+impl IrcTrim {
+    /// The trim in use
+    pub fn read(mcg: &MCG) -> Self {
+        let c4 = mcg.c4.read();
+        IrcTrim {
+            slow: mcg.c3.read().sctrim().bits(),
+            slow_fine: c4.scftrim().bit(),
+            fast: c4.fctrim().bits(),
+            fast_fine: mcg.c2.read().fcftrim().bit(),
+        }
+    }
+
+    /// Loads the trim into the MCG
+    pub fn apply(&self, mcg: &MCG) {
+        mcg.c3.write(|w| unsafe { w.sctrim().bits(self.slow) });
+        mcg.c4.modify(|_, w| unsafe {
+            w.scftrim().bit(self.slow_fine)
+                .fctrim().bits(self.fast & 0xF)
+        });
+        mcg.c2.modify(|_, w| w.fcftrim().bit(self.fast_fine));
+    }
+
+    /// Packs the trim into SCTRIM, SCFTRIM, FCTRIM and FCFTRIM from the
+    /// least significant bit up, with 0b01 in the top two bits marking it
+    /// as valid
+    pub fn to_bits(&self) -> u16 {
+        MARKER
+            | self.slow as u16
+            | (self.slow_fine as u16) << 8
+            | ((self.fast & 0xF) as u16) << 9
+            | (self.fast_fine as u16) << 13
+    }
+
+    /// Unpacks a trim from `to_bits`, `None` without the marker
+    pub fn from_bits(bits: u16) -> Option<Self> {
+        if bits & MARKER_MASK != MARKER {
+            return None;
+        }
+        Some(IrcTrim {
+            slow: bits as u8,
+            slow_fine: bits & (1 << 8) != 0,
+            fast: ((bits >> 9) & 0xF) as u8,
+            fast_fine: bits & (1 << 13) != 0,
+        })
+    }
+}
+
+/// Trims `irc` to its nominal frequency with the ATM, measuring it against
+/// the bus clock, and returns the trim of both references
+///
+/// Needs one of the external reference modes and a bus clock of 8 to
+/// 16 MHz. The result is already applied.
+pub fn auto_trim(mcg: &MCG, clocks: &Clocks, irc: Irc) -> Result<IrcTrim, TrimError> {
+    match clocks.mode() {
+        Mode::Fei | Mode::Fbi | Mode::Blpi => return Err(TrimError::Mode),
+        _ => {}
+    }
+    let bus = clocks.bus().0;
+    if !(8_000_000..=16_000_000).contains(&bus) {
+        return Err(TrimError::BusFrequency);
+    }
+    // Bus clock cycles in 21 periods of the slow IRC or 128 of the fast one
+    let compare = match irc {
+        Irc::Slow => bus as u64 * 21 / SLOW_IRC.0 as u64,
+        Irc::Fast => bus as u64 * 128 / FAST_IRC.0 as u64,
+    } as u16;
+    mcg.atcvh.write(|w| unsafe { w.bits((compare >> 8) as u8) });
+    mcg.atcvl.write(|w| unsafe { w.bits(compare as u8) });
+    // Clears a stale ATMF and LOCS0 is cleared by writing one, keep it
+    mcg.sc.modify(|_, w| {
+        w.atms().bit(irc == Irc::Fast)
+            .atmf().set_bit()
+            .locs0().clear_bit()
+    });
+    mcg.sc.modify(|_, w| w.atme().set_bit().atmf().clear_bit().locs0().clear_bit());
+    while mcg.sc.read().atme().bit() {}
+    if mcg.sc.read().atmf().bit() {
+        mcg.sc.modify(|_, w| w.atmf().set_bit().locs0().clear_bit());
+        return Err(TrimError::Failed);
+    }
+    Ok(IrcTrim::read(mcg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_round_trip() {
+        let trim = IrcTrim { slow: 0xA5, slow_fine: true, fast: 0x9, fast_fine: false };
+        assert_eq!(IrcTrim::from_bits(trim.to_bits()), Some(trim));
+        let trim = IrcTrim { slow: 0xFF, slow_fine: true, fast: 0xF, fast_fine: true };
+        assert_eq!(trim.to_bits(), 0x7FFF);
+        assert_eq!(IrcTrim::from_bits(trim.to_bits()), Some(trim));
+        let trim = IrcTrim { slow: 0, slow_fine: false, fast: 0, fast_fine: false };
+        assert_eq!(trim.to_bits(), 0x4000);
+        assert_eq!(IrcTrim::from_bits(trim.to_bits()), Some(trim));
+    }
+
+    #[test]
+    fn erased_and_zeroed_words_are_rejected() {
+        assert_eq!(IrcTrim::from_bits(0xFFFF), None);
+        assert_eq!(IrcTrim::from_bits(0x0000), None);
+        assert_eq!(IrcTrim::from_bits(0xBFFF), None);
+    }
+}
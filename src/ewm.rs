@@ -0,0 +1,161 @@
+//! External watchdog monitor (EWM)
+//!
+//! The EWM asserts EWM_OUT, e.g. to reset external circuitry, when it is
+//! not serviced within its window or when EWM_IN is asserted. It counts the
+//! 1 kHz LPO, so the window is at most 254 ms. The configuration can only
+//! be written once after reset.
+
+use core::ptr;
+use cortex_m::interrupt;
+use crate::clocks::gate::Enable;
+use crate::gpio::*;
+use crate::pac::EWM;
+use crate::time::MilliSeconds;
+
+const SERVICE_KEY1: u8 = 0xB4;
+const SERVICE_KEY2: u8 = 0x2C;
+
+/// Largest CMPH value
+const MAX_COMPARE_HIGH: u8 = 0xFE;
+
+pub trait InPin {}
+pub trait OutPin {}
+
+macro_rules! ewm_pins {
+    (in => [$($inpin:ty,)+], out => [$($outpin:ty,)+]) => {
+        $(
+            impl InPin for $inpin {}
+        )+
+        $(
+            impl OutPin for $outpin {}
+        )+
+    }
+}
+
+ewm_pins! {
+    in => [
+        portb::PB16<Alternative<AF6>>,
+        portd::PD4<Alternative<AF6>>,
+        porte::PE25<Alternative<AF6>>,
+    ],
+    out => [
+        portb::PB17<Alternative<AF6>>,
+        portd::PD5<Alternative<AF6>>,
+        porte::PE24<Alternative<AF6>>,
+    ]
+}
+
+/// Level of EWM_IN that asserts EWM_OUT
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputPolarity {
+    Low,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EwmError {
+    /// The window is empty once both ends are limited to 254 ms
+    Window,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EwmConfig {
+    compare_low: u8,
+    compare_high: u8,
+    interrupt: bool,
+}
+
+impl Default for EwmConfig {
+    /// No lower bound and the longest timeout
+    fn default() -> Self {
+        EwmConfig { compare_low: 0, compare_high: MAX_COMPARE_HIGH, interrupt: false }
+    }
+}
+
+impl EwmConfig {
+    /// Servicing is only accepted between `min` and `max` after the last
+    /// service. Both are limited to 254 ms, after which `min` has to stay
+    /// below `max`.
+    pub fn window(mut self, min: MilliSeconds, max: MilliSeconds) -> Result<Self, EwmError> {
+        let ticks = |duration: MilliSeconds| duration.0.min(MAX_COMPARE_HIGH as u32) as u8;
+        let (compare_low, compare_high) = (ticks(min), ticks(max));
+        if compare_low >= compare_high {
+            return Err(EwmError::Window);
+        }
+        self.compare_low = compare_low;
+        self.compare_high = compare_high;
+        Ok(self)
+    }
+
+    /// Raises the WDOG_EWM interrupt along with EWM_OUT
+    pub fn interrupt(mut self, enabled: bool) -> Self {
+        self.interrupt = enabled;
+        self
+    }
+}
+
+pub struct Ewm<OUTPIN, INPIN> {
+    ewm: EWM,
+    pins: (OUTPIN, INPIN),
+}
+
+impl<OUTPIN> Ewm<OUTPIN, ()>
+    where
+        OUTPIN: OutPin
+{
+    /// Starts the EWM with EWM_IN ignored
+    pub fn new(ewm: EWM, outpin: OUTPIN, config: &EwmConfig) -> Self {
+        let ewm = Ewm { ewm, pins: (outpin, ()) };
+        ewm.configure(config, None);
+        ewm
+    }
+}
+
+impl<OUTPIN, INPIN> Ewm<OUTPIN, INPIN>
+    where
+        OUTPIN: OutPin,
+        INPIN: InPin
+{
+    /// Starts the EWM, also asserting EWM_OUT when EWM_IN is at `polarity`
+    pub fn with_input(ewm: EWM, pins: (OUTPIN, INPIN), polarity: InputPolarity, config: &EwmConfig) -> Self {
+        let ewm = Ewm { ewm, pins };
+        ewm.configure(config, Some(polarity));
+        ewm
+    }
+}
+
+impl<OUTPIN, INPIN> Ewm<OUTPIN, INPIN> {
+    fn configure(&self, config: &EwmConfig, input: Option<InputPolarity>) {
+        EWM::enable();
+        self.ewm.cmpl.write(|w| unsafe { w.bits(config.compare_low) });
+        self.ewm.cmph.write(|w| unsafe { w.bits(config.compare_high) });
+        self.ewm.ctrl.write(|w| {
+            w.ewmen().set_bit()
+                .assin().bit(input == Some(InputPolarity::High))
+                .inen().bit(input.is_some())
+                .inten().bit(config.interrupt)
+        });
+    }
+
+    /// Restarts the counter, the two writes must not be interrupted
+    pub fn service(&mut self) {
+        // The second key has to follow within 15 bus clocks, even in an
+        // unoptimized build, so both are stored directly
+        let serv = &self.ewm.serv as *const _ as *mut u8;
+        interrupt::free(|_| unsafe {
+            ptr::write_volatile(serv, SERVICE_KEY1);
+            ptr::write_volatile(serv, SERVICE_KEY2);
+        })
+    }
+
+    /// The only setting that can be changed after start, disabling the
+    /// interrupt also clears a pending request
+    pub fn set_interrupt(&mut self, enabled: bool) {
+        self.ewm.ctrl.modify(|_, w| w.inten().bit(enabled));
+    }
+
+    /// Gives the peripheral and pins back, the EWM keeps running
+    pub fn free(self) -> (EWM, (OUTPIN, INPIN)) {
+        (self.ewm, self.pins)
+    }
+}
@@ -42,8 +42,8 @@ pub struct Output<MODE> {
     _mode: PhantomData<MODE>
 }
 
-pub struct Alternative<AF> {
-    _mode: PhantomData<AF>,
+pub struct Alternative<AF, OTYPE = PushPull> {
+    _mode: PhantomData<(AF, OTYPE)>,
 }
 
 pub struct Pin<MODE> {
@@ -150,11 +150,12 @@ macro_rules! gpio {
             pub mod $portx {
                 use core::{marker::PhantomData, convert::Infallible};
                 use embedded_hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, toggleable};
-                use crate::pac::{$PORTX, $GPIOX, SIM};
+                use crate::pac::{$PORTX, $GPIOX};
+                use crate::clocks::gate::Enable;
                 use cortex_m::interrupt::CriticalSection;
 
                 use super::{
-                    Alternative, GpioExt, Input, OpenDrain, Output, Floating, PullUp, PullDown,
+                    Alternative, GpioExt, Input, OpenDrain, Output, Floating, PullUp, PullDown, PushPull,
                     AF0, AF1, AF2, AF3, AF4, AF5, AF6, AF7,
                     Pin, GpioRegExt,
                 };
@@ -169,7 +170,7 @@ macro_rules! gpio {
                     type Parts = Parts;
 
                     fn split(self) -> Parts {
-                        unsafe { (&*SIM::ptr()) }.scgc5.modify(|_, w| w.$portx().set_bit());
+                        $PORTX::enable();
                         Parts {
                             $(
                               $pxi: $PXi { _mode: PhantomData },
@@ -255,6 +256,15 @@ macro_rules! gpio {
                         }
                     }
 
+                    impl<AF> $PXi<Alternative<AF, PushPull>> {
+                        pub fn into_open_drain(
+                            self, _cs: &CriticalSection
+                        ) -> $PXi<Alternative<AF, OpenDrain>> {
+                            unsafe { (&*$PORTX::ptr()) }.$pcri.modify(|_, w| w.ode().set_bit());
+                            $PXi { _mode: PhantomData }
+                        }
+                    }
+
 
                     impl<MODE> $PXi<Output<MODE>> {
                         pub fn downgrade(self) -> Pin<Output<MODE>> {
@@ -1,9 +1,14 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 pub use k64 as pac;
 
 pub mod gpio;
 pub mod uart;
 pub mod adc;
+pub mod clocks;
 pub mod time;
-pub mod prelude;
\ No newline at end of file
+pub mod prelude;
+pub mod shell;
+pub mod wdog;
+pub mod ewm;
+pub mod rcm;
\ No newline at end of file
@@ -0,0 +1,132 @@
+//! Reset control module (RCM), the cause of the last reset and the RESET
+//! pin filter
+
+use crate::pac::RCM;
+
+/// Sources of a reset, as flagged in SRS0 and SRS1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetCause {
+    /// Wakeup from a VLLS mode through the LLWU
+    Wakeup = 0,
+    /// Low-voltage detect
+    LowVoltage = 1,
+    /// Loss of the external clock, see `clocks::monitor`
+    LossOfClock = 2,
+    /// Loss of PLL lock
+    LossOfLock = 3,
+    /// WDOG timeout
+    Watchdog = 5,
+    /// RESET pin
+    Pin = 6,
+    /// Power-on, the other causes are not flagged along with it
+    PowerOn = 7,
+    /// JTAG reset
+    Jtag = 8,
+    /// Core lockup
+    Lockup = 9,
+    /// SYSRESETREQ set by software
+    Software = 10,
+    /// Debugger reset through the MDM-AP
+    MdmAp = 11,
+    /// EzPort reset
+    EzPort = 12,
+    /// Stop mode acknowledge error
+    StopAckError = 13,
+}
+
+const CAUSES: [ResetCause; 13] = [
+    ResetCause::Wakeup,
+    ResetCause::LowVoltage,
+    ResetCause::LossOfClock,
+    ResetCause::LossOfLock,
+    ResetCause::Watchdog,
+    ResetCause::Pin,
+    ResetCause::PowerOn,
+    ResetCause::Jtag,
+    ResetCause::Lockup,
+    ResetCause::Software,
+    ResetCause::MdmAp,
+    ResetCause::EzPort,
+    ResetCause::StopAckError,
+];
+
+/// Every cause flagged for the last reset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResetCauses {
+    /// SRS0 in the low byte, SRS1 in the high byte
+    bits: u16,
+}
+
+impl ResetCauses {
+    pub fn contains(&self, cause: ResetCause) -> bool {
+        self.bits & (1 << cause as u16) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ResetCause> {
+        let causes = *self;
+        CAUSES.iter().copied().filter(move |cause| causes.contains(*cause))
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.bits
+    }
+}
+
+/// Clock of the RESET pin filter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterClock {
+    Disabled,
+    Bus,
+    Lpo,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PinFilter {
+    /// Filter in run and wait modes
+    pub run: FilterClock,
+    /// LPO filter in stop modes, the bus clock is not available there
+    pub stop: bool,
+    /// Clocks the pin has to stay asserted for, 1 to 32, when filtering
+    /// with the bus clock
+    pub width: u8,
+}
+
+pub struct Rcm {
+    rcm: RCM,
+}
+
+impl Rcm {
+    pub fn new(rcm: RCM) -> Self {
+        Rcm { rcm }
+    }
+
+    /// Causes of the last reset, the flags are only updated by the next
+    /// reset
+    pub fn reset_causes(&self) -> ResetCauses {
+        let srs0 = self.rcm.srs0.read().bits();
+        let srs1 = self.rcm.srs1.read().bits();
+        ResetCauses { bits: srs0 as u16 | (srs1 as u16) << 8 }
+    }
+
+    pub fn set_pin_filter(&mut self, filter: &PinFilter) {
+        let run = match filter.run {
+            FilterClock::Disabled => 0b00,
+            FilterClock::Bus => 0b01,
+            FilterClock::Lpo => 0b10,
+        };
+        let width = filter.width.clamp(1, 32) - 1;
+        self.rcm.rpfw.write(|w| unsafe { w.rstfltsel().bits(width) });
+        self.rcm.rpfc.write(|w| unsafe {
+            w.rstfltsrw().bits(run)
+                .rstfltss().bit(filter.stop)
+        });
+    }
+
+    pub fn free(self) -> RCM {
+        self.rcm
+    }
+}
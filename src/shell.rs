@@ -0,0 +1,372 @@
+//! Debug console with line editing
+//!
+//! Commands live in a static table, arguments are split on whitespace and
+//! double quotes group words into one argument:
+//!
+//! ```ignore
+//! static COMMANDS: &[Command] = &[
+//!     Command { name: "led", help: "led <on|off>", handler: led },
+//! ];
+//!
+//! let mut shell = Shell::new(COMMANDS, "> ");
+//! loop {
+//!     shell.poll(&mut serial);
+//! }
+//! ```
+//!
+//! The line editor understands backspace, Ctrl-C and the up and down arrow
+//! keys to walk through the history of entered lines.
+
+use core::fmt::{self, Write};
+use embedded_hal::serial::Read;
+
+/// Longest line the editor accepts
+pub const LINE_LEN: usize = 80;
+
+/// Entered lines kept for recall
+pub const HISTORY_LEN: usize = 4;
+
+/// Most arguments passed to a command, its name included
+pub const MAX_ARGS: usize = 8;
+
+pub struct Command {
+    pub name: &'static str,
+    /// One line shown by `help`
+    pub help: &'static str,
+    /// Receives the arguments following the command name
+    pub handler: fn(&mut dyn Write, &[&str]),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TokenizeError {
+    TooManyArgs,
+    UnterminatedQuote,
+}
+
+/// Splits `line` into `args`, returning how many were found
+pub fn tokenize<'a>(line: &'a str, args: &mut [&'a str; MAX_ARGS]) -> Result<usize, TokenizeError> {
+    let mut count = 0;
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (arg, remainder) = if let Some(quoted) = rest.strip_prefix('"') {
+            match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => return Err(TokenizeError::UnterminatedQuote),
+            }
+        } else {
+            match rest.find(char::is_whitespace) {
+                Some(end) => (&rest[..end], &rest[end..]),
+                None => (rest, ""),
+            }
+        };
+        if count == MAX_ARGS {
+            return Err(TokenizeError::TooManyArgs);
+        }
+        args[count] = arg;
+        count += 1;
+        rest = remainder.trim_start();
+    }
+    Ok(count)
+}
+
+/// Key presses the editor reacts to
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(u8),
+    Backspace,
+    Enter,
+    Cancel,
+    Up,
+    Down,
+    None,
+}
+
+#[derive(Clone, Copy)]
+enum Escape {
+    Idle,
+    Started,
+    Bracket,
+}
+
+#[derive(Clone, Copy)]
+struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Line = Line { buf: [0; LINE_LEN], len: 0 };
+
+    fn as_str(&self) -> &str {
+        // Only printable ASCII is ever stored
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+struct History {
+    lines: [Line; HISTORY_LEN],
+    /// Number of stored lines
+    len: usize,
+    /// Slot the next line is stored in
+    next: usize,
+}
+
+impl History {
+    fn push(&mut self, line: &Line) {
+        if line.len == 0 {
+            return;
+        }
+        self.lines[self.next] = *line;
+        self.next = (self.next + 1) % HISTORY_LEN;
+        self.len = (self.len + 1).min(HISTORY_LEN);
+    }
+
+    /// `age` 1 is the most recent line
+    fn get(&self, age: usize) -> Option<&Line> {
+        if age == 0 || age > self.len {
+            return None;
+        }
+        Some(&self.lines[(self.next + HISTORY_LEN - age) % HISTORY_LEN])
+    }
+}
+
+pub struct Shell {
+    commands: &'static [Command],
+    prompt: &'static str,
+    line: Line,
+    history: History,
+    /// How far back in the history the line being edited was recalled from
+    recalled: usize,
+    escape: Escape,
+    /// Swallows the `\n` of a `\r\n` line ending
+    after_cr: bool,
+}
+
+impl Shell {
+    pub fn new(commands: &'static [Command], prompt: &'static str) -> Self {
+        Shell {
+            commands,
+            prompt,
+            line: Line::EMPTY,
+            history: History { lines: [Line::EMPTY; HISTORY_LEN], len: 0, next: 0 },
+            recalled: 0,
+            escape: Escape::Idle,
+            after_cr: false,
+        }
+    }
+
+    /// Prints the prompt
+    pub fn start(&mut self, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(self.prompt)
+    }
+
+    /// Feeds every byte the serial port has received so far to the shell
+    pub fn poll<S>(&mut self, serial: &mut S) -> fmt::Result
+        where
+            S: Read<u8> + Write
+    {
+        while let Ok(byte) = serial.read() {
+            self.feed(byte, serial)?;
+        }
+        Ok(())
+    }
+
+    /// Handles one received byte, echoing it and running the command when a
+    /// line is complete
+    pub fn feed(&mut self, byte: u8, out: &mut dyn Write) -> fmt::Result {
+        match self.decode(byte) {
+            Key::Char(c) if self.line.len < LINE_LEN => {
+                self.line.buf[self.line.len] = c;
+                self.line.len += 1;
+                out.write_char(c as char)
+            }
+            Key::Backspace if self.line.len > 0 => {
+                self.line.len -= 1;
+                out.write_str("\x08 \x08")
+            }
+            Key::Cancel => {
+                self.line.len = 0;
+                self.recalled = 0;
+                write!(out, "^C\r\n{}", self.prompt)
+            }
+            Key::Up => self.recall(self.recalled + 1, out),
+            Key::Down if self.recalled > 1 => self.recall(self.recalled - 1, out),
+            Key::Down if self.recalled == 1 => {
+                self.recalled = 0;
+                self.line.len = 0;
+                self.redraw(out)
+            }
+            Key::Enter => {
+                out.write_str("\r\n")?;
+                let line = self.line;
+                self.history.push(&line);
+                self.line.len = 0;
+                self.recalled = 0;
+                self.execute(line.as_str(), out)?;
+                out.write_str(self.prompt)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs a command line
+    pub fn execute(&self, line: &str, out: &mut dyn Write) -> fmt::Result {
+        let mut args = [""; MAX_ARGS];
+        let count = match tokenize(line, &mut args) {
+            Ok(0) => return Ok(()),
+            Ok(count) => count,
+            Err(TokenizeError::TooManyArgs) => return out.write_str("too many arguments\r\n"),
+            Err(TokenizeError::UnterminatedQuote) => return out.write_str("unterminated quote\r\n"),
+        };
+        if args[0] == "help" {
+            return self.help(out);
+        }
+        match self.commands.iter().find(|command| command.name == args[0]) {
+            Some(command) => {
+                (command.handler)(out, &args[1..count]);
+                Ok(())
+            }
+            None => write!(out, "unknown command: {}, try help\r\n", args[0]),
+        }
+    }
+
+    fn help(&self, out: &mut dyn Write) -> fmt::Result {
+        let width = self.commands.iter().map(|command| command.name.len()).max().unwrap_or(0).max(4);
+        write!(out, "{:width$}  {}\r\n", "help", "list commands", width = width)?;
+        for command in self.commands {
+            write!(out, "{:width$}  {}\r\n", command.name, command.help, width = width)?;
+        }
+        Ok(())
+    }
+
+    fn recall(&mut self, age: usize, out: &mut dyn Write) -> fmt::Result {
+        if let Some(line) = self.history.get(age) {
+            self.line = *line;
+            self.recalled = age;
+            self.redraw(out)?;
+        }
+        Ok(())
+    }
+
+    fn redraw(&self, out: &mut dyn Write) -> fmt::Result {
+        write!(out, "\r\x1b[K{}{}", self.prompt, self.line.as_str())
+    }
+
+    fn decode(&mut self, byte: u8) -> Key {
+        let after_cr = core::mem::replace(&mut self.after_cr, false);
+        match (self.escape, byte) {
+            (Escape::Started, b'[') => {
+                self.escape = Escape::Bracket;
+                Key::None
+            }
+            (Escape::Bracket, b'A') => {
+                self.escape = Escape::Idle;
+                Key::Up
+            }
+            (Escape::Bracket, b'B') => {
+                self.escape = Escape::Idle;
+                Key::Down
+            }
+            (Escape::Started, _) | (Escape::Bracket, _) => {
+                self.escape = Escape::Idle;
+                Key::None
+            }
+            (Escape::Idle, 0x1B) => {
+                self.escape = Escape::Started;
+                Key::None
+            }
+            (Escape::Idle, b'\r') => {
+                self.after_cr = true;
+                Key::Enter
+            }
+            (Escape::Idle, b'\n') if after_cr => Key::None,
+            (Escape::Idle, b'\n') => Key::Enter,
+            (Escape::Idle, 0x08) | (Escape::Idle, 0x7F) => Key::Backspace,
+            (Escape::Idle, 0x03) => Key::Cancel,
+            (Escape::Idle, 0x20..=0x7E) => Key::Char(byte),
+            (Escape::Idle, _) => Key::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+
+    fn echo(out: &mut dyn Write, args: &[&str]) {
+        let _ = write!(out, "echo:{}\r\n", args.join("|"));
+    }
+
+    static COMMANDS: &[Command] = &[Command { name: "echo", help: "echo <args>", handler: echo }];
+
+    fn feed(shell: &mut Shell, input: &[u8]) -> String {
+        let mut out = String::new();
+        for byte in input {
+            shell.feed(*byte, &mut out).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn tokenize_splits_on_whitespace() {
+        let mut args = [""; MAX_ARGS];
+        assert_eq!(tokenize("  led  on \t", &mut args), Ok(2));
+        assert_eq!(&args[..2], &["led", "on"]);
+        assert_eq!(tokenize("   ", &mut args), Ok(0));
+    }
+
+    #[test]
+    fn tokenize_groups_quoted_words() {
+        let mut args = [""; MAX_ARGS];
+        assert_eq!(tokenize("say \"hello world\" \"\"", &mut args), Ok(3));
+        assert_eq!(&args[..3], &["say", "hello world", ""]);
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        let mut args = [""; MAX_ARGS];
+        assert_eq!(tokenize("a b c d e f g h i", &mut args), Err(TokenizeError::TooManyArgs));
+        assert_eq!(tokenize("say \"hello", &mut args), Err(TokenizeError::UnterminatedQuote));
+    }
+
+    #[test]
+    fn feed_runs_command_once_per_crlf() {
+        let mut shell = Shell::new(COMMANDS, "> ");
+        let out = feed(&mut shell, b"echo a \"b c\"\r\n");
+        assert_eq!(out, "echo a \"b c\"\r\necho:a|b c\r\n> ");
+    }
+
+    #[test]
+    fn feed_handles_backspace() {
+        let mut shell = Shell::new(COMMANDS, "> ");
+        let out = feed(&mut shell, b"echo ab\x08c\x7F\x7Fd\r");
+        assert_eq!(out, "echo ab\x08 \x08c\x08 \x08\x08 \x08d\r\necho:d\r\n> ");
+    }
+
+    #[test]
+    fn feed_cancels_line_on_ctrl_c() {
+        let mut shell = Shell::new(COMMANDS, "> ");
+        let out = feed(&mut shell, b"echo x\x03\r");
+        assert_eq!(out, "echo x^C\r\n> \r\n> ");
+    }
+
+    #[test]
+    fn feed_recalls_history() {
+        let mut shell = Shell::new(COMMANDS, "> ");
+        feed(&mut shell, b"echo 1\recho 2\r");
+        let out = feed(&mut shell, b"\x1b[A\x1b[A");
+        assert_eq!(out, "\r\x1b[K> echo 2\r\x1b[K> echo 1");
+        let out = feed(&mut shell, b"\x1b[B\x1b[B");
+        assert_eq!(out, "\r\x1b[K> echo 2\r\x1b[K> ");
+        let out = feed(&mut shell, b"\x1b[A\r");
+        assert_eq!(out, "\r\x1b[K> echo 2\r\necho:2\r\n> ");
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut shell = Shell::new(COMMANDS, "> ");
+        let out = feed(&mut shell, b"nope\r");
+        assert_eq!(out, "nope\r\nunknown command: nope, try help\r\n> ");
+    }
+}
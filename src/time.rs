@@ -2,7 +2,8 @@
 //! Time units
 
 use core::fmt;
-use cortex_m::peripheral::DWT;
+use cortex_m::peripheral::{DCB, DWT};
+use crate::clocks::Clocks;
 
 /// Bits per second
 #[derive(Clone, Copy, Debug)]
@@ -130,6 +131,67 @@ impl Into<Hertz> for MilliSeconds {
         Hertz(1_000 / period)
     }
 }
+/// A monotonic nondecreasing timer, counting core clock cycles
+#[derive(Clone, Copy)]
+pub struct MonoTimer {
+    frequency: Hertz,
+}
+
+impl MonoTimer {
+    /// Creates a new `Monotonic` timer
+    pub fn new(mut dwt: DWT, mut dcb: DCB, clocks: &Clocks) -> Self {
+        dcb.enable_trace();
+        dwt.enable_cycle_counter();
+
+        // now the CYCCNT counter can't be stopped or reset
+        drop(dwt);
+
+        MonoTimer { frequency: clocks.core() }
+    }
+
+    /// Returns the frequency at which the monotonic timer is operating at
+    pub fn frequency(&self) -> Hertz {
+        self.frequency
+    }
+
+    /// Returns an `Instant` corresponding to "now"
+    pub fn now(&self) -> Instant {
+        Instant {
+            now: DWT::get_cycle_count(),
+        }
+    }
+
+    /// Number of ticks in `duration`, which may exceed the 32 bit counter
+    /// range of about 35 s at 120 MHz
+    pub fn ticks(&self, duration: MilliSeconds) -> u64 {
+        duration.0 as u64 * self.frequency.0 as u64 / 1_000
+    }
+
+    /// Starts measuring `timeout` from now
+    pub fn deadline(&self, timeout: MilliSeconds) -> Deadline {
+        Deadline { last: self.now(), elapsed: 0, ticks: self.ticks(timeout) }
+    }
+}
+
+/// A timeout that may be longer than the period of the cycle counter
+///
+/// Counter wraps are accounted for as long as `expired` is polled at least
+/// once per counter period.
+pub struct Deadline {
+    last: Instant,
+    elapsed: u64,
+    ticks: u64,
+}
+
+impl Deadline {
+    pub fn expired(&mut self) -> bool {
+        let now = DWT::get_cycle_count();
+        self.elapsed += now.wrapping_sub(self.last.now) as u64;
+        self.last = Instant { now };
+        self.elapsed >= self.ticks
+    }
+}
+
 /// A measurement of a monotonically nondecreasing clock
 #[derive(Clone, Copy)]
 pub struct Instant {
@@ -141,4 +203,9 @@ impl Instant {
     pub fn elapsed(&self) -> u32 {
         DWT::get_cycle_count().wrapping_sub(self.now)
     }
+
+    /// Ticks from `earlier` to this `Instant`
+    pub fn duration_since(&self, earlier: Instant) -> u32 {
+        self.now.wrapping_sub(earlier.now)
+    }
 }
\ No newline at end of file
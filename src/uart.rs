@@ -1,14 +1,107 @@
 use embedded_hal::serial::{Read, Write};
 use core::{marker::PhantomData, convert::Infallible};
-use crate::pac::SIM;
+use core::sync::atomic::{AtomicU32, Ordering};
 use crate::gpio::*;
+use crate::clocks::Clocks;
+use crate::clocks::gate::{Disable, Enable};
+use crate::time::{Bps, Hertz, MilliSeconds, MonoTimer};
 
-#[derive(Debug)]
+pub mod iso7816;
+pub mod lin;
+#[cfg(feature = "embedded-io")]
+mod io;
+#[cfg(feature = "embedded-hal-nb")]
+mod hal_nb;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod wakeup;
+#[cfg(any(feature = "log", feature = "defmt"))]
+pub mod logger;
+
+/// Receive errors, carrying the received byte where the hardware keeps it
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum UartError {
-    Framing,
-    Noise,
-    Overrun,
-    Parity,
+    Framing(u8),
+    Noise(u8),
+    /// A byte was lost, the one returned arrived before it and is valid
+    Overrun(u8),
+    Parity(u8),
+    /// All zero character without a stop bit
+    Break,
+    /// Nothing was received within the timeout
+    Timeout,
+}
+
+/// A nine bit value was written while the UART is configured for eight data
+/// bits
+#[derive(Debug)]
+pub struct WordLengthError;
+
+/// Whether C3.R8/T8 hold the ninth data bit. With parity enabled in the 9 bit
+/// frame they hold the parity bit instead, the 10 bit frame (C4.M10) puts the
+/// ninth data bit there and the parity bit after it.
+fn nine_bit_data(m: bool, pe: bool, m10: bool) -> bool {
+    m && (!pe || m10)
+}
+
+/// Number of receive errors seen on one UART since the last reset
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ErrorCounts {
+    pub framing: u32,
+    pub noise: u32,
+    pub overrun: u32,
+    pub parity: u32,
+    pub breaks: u32,
+}
+
+struct ErrorCounters {
+    framing: AtomicU32,
+    noise: AtomicU32,
+    overrun: AtomicU32,
+    parity: AtomicU32,
+    breaks: AtomicU32,
+}
+
+impl ErrorCounters {
+    const fn new() -> Self {
+        ErrorCounters {
+            framing: AtomicU32::new(0),
+            noise: AtomicU32::new(0),
+            overrun: AtomicU32::new(0),
+            parity: AtomicU32::new(0),
+            breaks: AtomicU32::new(0),
+        }
+    }
+
+    fn record(&self, error: &UartError) {
+        let counter = match error {
+            UartError::Framing(_) => &self.framing,
+            UartError::Noise(_) => &self.noise,
+            UartError::Overrun(_) => &self.overrun,
+            UartError::Parity(_) => &self.parity,
+            UartError::Break => &self.breaks,
+            UartError::Timeout => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> ErrorCounts {
+        ErrorCounts {
+            framing: self.framing.load(Ordering::Relaxed),
+            noise: self.noise.load(Ordering::Relaxed),
+            overrun: self.overrun.load(Ordering::Relaxed),
+            parity: self.parity.load(Ordering::Relaxed),
+            breaks: self.breaks.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.framing.store(0, Ordering::Relaxed);
+        self.noise.store(0, Ordering::Relaxed);
+        self.overrun.store(0, Ordering::Relaxed);
+        self.parity.store(0, Ordering::Relaxed);
+        self.breaks.store(0, Ordering::Relaxed);
+    }
 }
 
 pub struct BaudRate(pub u32);
@@ -19,46 +112,153 @@ impl Into<BaudRate> for u32 {
     }
 }
 
-#[derive(PartialOrd, PartialEq)]
+impl From<BaudRate> for Bps {
+    fn from(baudrate: BaudRate) -> Self {
+        Bps(baudrate.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialOrd, PartialEq)]
 pub enum WordLength {
     DataBits8,
     DataBits9,
 }
 
-#[derive(PartialOrd, PartialEq)]
+#[derive(Clone, Copy, PartialOrd, PartialEq)]
 pub enum Parity {
     None,
     Even,
     Odd,
 }
 
-#[derive(PartialOrd, PartialEq)]
+#[derive(Clone, Copy, PartialOrd, PartialEq)]
 pub enum StopBits {
     Stop1,
     Stop2,
 }
 
+/// IrDA transmit pulse width, as a fraction of a bit time
+#[derive(Clone, Copy, PartialOrd, PartialEq)]
+pub enum IrdaPulseWidth {
+    ThreeSixteenths,
+    OneSixteenth,
+    OneThirtySecond,
+    OneQuarter,
+}
+
+#[derive(Clone, Copy)]
 pub struct Config {
-    baudrate: BaudRate,
+    baudrate: Bps,
     word_length: WordLength,
     parity: Parity,
-    stop_bits: StopBits
+    stop_bits: StopBits,
+    irda: Option<IrdaPulseWidth>,
+    invert_rx: bool,
+    invert_tx: bool,
+    msb_first: bool,
+}
+
+/// Outcome of `Serial::self_test`, one counter per kind of failure
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SelfTestReport {
+    pub sent: usize,
+    pub received: usize,
+    pub mismatch: usize,
+    pub overrun: usize,
+    pub framing: usize,
+    pub noise: usize,
+    pub parity: usize,
+    pub timeout: usize,
 }
 
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.sent == self.received && self.mismatch == 0
+            && self.overrun == 0 && self.framing == 0
+            && self.noise == 0 && self.parity == 0
+            && self.timeout == 0
+    }
+}
+
+/// Status polls to wait for a looped back byte before counting a timeout
+const SELF_TEST_POLLS: u32 = 1_000_000;
+
+/// UART status interrupt sources
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// A byte was received (S1.RDRF)
+    ReceiveDataFull,
+    /// The transmit buffer accepts a byte (S1.TDRE)
+    TransmitDataEmpty,
+    /// The last byte left the shift register (S1.TC)
+    TransmissionComplete,
+    /// The receive line went idle after a frame (S1.IDLE)
+    Idle,
+    /// Start of a character on the RX pin, also in stop modes (S2.RXEDGIF)
+    RxActiveEdge,
+}
+
+/// Rates `Serial::auto_baud` chooses from
+pub const STANDARD_BAUDRATES: [u32; 12] = [
+    1_200, 2_400, 4_800, 9_600, 14_400, 19_200,
+    38_400, 57_600, 115_200, 230_400, 460_800, 921_600,
+];
+
+/// Standard rate closest to a measured one, by ratio rather than difference
+pub fn nearest_standard_baudrate(measured: Bps) -> Bps {
+    let measured = measured.0.max(1) as u64;
+    let distance = |rate: u64| if measured > rate {
+        measured * 1_000 / rate
+    } else {
+        rate * 1_000 / measured
+    };
+    let rate = STANDARD_BAUDRATES.iter()
+        .min_by_key(|rate| distance(**rate as u64))
+        .unwrap();
+    Bps(*rate)
+}
+
+/// Falling edges of the `0x55` sync character, from the start bit to the
+/// last data bit, eight bit times apart
+const AUTO_BAUD_EDGES: u32 = 5;
+const AUTO_BAUD_BITS: u64 = 8;
+
 pub trait RxPin<UART> { }
 pub trait TxPin<UART> { }
 
+/// TX pins in open-drain mode, able to share the line in half-duplex mode
+pub trait HalfDuplexPin<UART>: TxPin<UART> { }
+
+/// Stands in for the RX pin of a half-duplex `Serial`, whose receiver listens
+/// on the TX pin
+pub struct SingleWire {
+    _private: (),
+}
+
+impl<UART> RxPin<UART> for SingleWire { }
+
+/// RX pins proper, unlike `SingleWire`
+pub trait DedicatedRxPin<UART>: RxPin<UART> { }
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum HalfDuplexDirection {
+    Receive,
+    Transmit,
+}
+
 macro_rules! uart_pins {
     ($($UART:ident => {
-        tx => [$($tx:ty), + $(,)*],
+        tx => [$($txport:ident::$tx:ident<$txaf:ty>), + $(,)*],
         rx => [$($rx:ty), + $(,)*],
     })+) => {
         $(
             $(
-                impl TxPin<crate::pac::$UART> for $tx { }
+                impl<OTYPE> TxPin<crate::pac::$UART> for $txport::$tx<Alternative<$txaf, OTYPE>> { }
+                impl HalfDuplexPin<crate::pac::$UART> for $txport::$tx<Alternative<$txaf, OpenDrain>> { }
             )+
             $(
                 impl RxPin<crate::pac::$UART> for $rx { }
+                impl DedicatedRxPin<crate::pac::$UART> for $rx { }
             )+
         )+
     }
@@ -66,8 +266,30 @@ macro_rules! uart_pins {
 
 uart_pins! {
     UART0 => {
-        tx => [portb::PB17<Alternative<AF3>>],
-        rx => [portb::PB16<Alternative<AF3>>],
+        tx => [porta::PA2<AF2>, porta::PA14<AF3>, portb::PB17<AF3>, portd::PD7<AF3>],
+        rx => [porta::PA1<Alternative<AF2>>, porta::PA15<Alternative<AF3>>,
+               portb::PB16<Alternative<AF3>>, portd::PD6<Alternative<AF3>>],
+    }
+    UART1 => {
+        tx => [portc::PC4<AF3>, porte::PE0<AF3>],
+        rx => [portc::PC3<Alternative<AF3>>, porte::PE1<Alternative<AF3>>],
+    }
+    UART2 => {
+        tx => [portd::PD3<AF3>],
+        rx => [portd::PD2<Alternative<AF3>>],
+    }
+    UART3 => {
+        tx => [portb::PB11<AF3>, portc::PC17<AF3>, porte::PE4<AF3>],
+        rx => [portb::PB10<Alternative<AF3>>, portc::PC16<Alternative<AF3>>,
+               porte::PE5<Alternative<AF3>>],
+    }
+    UART4 => {
+        tx => [portc::PC15<AF3>, porte::PE24<AF3>],
+        rx => [portc::PC14<Alternative<AF3>>, porte::PE25<Alternative<AF3>>],
+    }
+    UART5 => {
+        tx => [portd::PD9<AF3>, porte::PE8<AF3>],
+        rx => [portd::PD8<Alternative<AF3>>, porte::PE9<Alternative<AF3>>],
     }
 }
 
@@ -82,6 +304,8 @@ pub struct Tx<UART> {
 pub struct Serial<UART, TXPIN, RXPIN> {
     uart: UART,
     pins: (TXPIN, RXPIN),
+    /// Module clock the baud rate is derived from
+    clock: Hertz,
 }
 
 impl<UART, TXPIN, RXPIN> Serial<UART, TXPIN, RXPIN>
@@ -101,25 +325,166 @@ impl<UART, TXPIN, RXPIN> Serial<UART, TXPIN, RXPIN>
         )
     }
 
-    pub fn relase(self) -> (TXPIN, RXPIN) {
+    /// Gates the UART clock and gives the pins back
+    pub fn release(self) -> (TXPIN, RXPIN)
+        where
+            UART: Disable
+    {
+        // The driver is consumed, nothing accesses the UART any more
+        unsafe { UART::disable() };
         self.pins
     }
+
+    #[deprecated(note = "use `release`")]
+    pub fn relase(self) -> (TXPIN, RXPIN)
+        where
+            UART: Disable
+    {
+        self.release()
+    }
+}
+
+impl<UART> Rx<UART>
+    where
+        Rx<UART>: Read<u8, Error = UartError>
+{
+    /// Waits at most `timeout` for a byte
+    pub fn read_timeout(&mut self, timer: &MonoTimer, timeout: MilliSeconds) -> Result<u8, UartError> {
+        let mut deadline = timer.deadline(timeout);
+        loop {
+            match self.read() {
+                Ok(byte) => return Ok(byte),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) if deadline.expired() => return Err(UartError::Timeout),
+                Err(nb::Error::WouldBlock) => {},
+            }
+        }
+    }
+
+    /// Fills `buf`, waiting at most `timeout` for the whole of it
+    pub fn read_exact_timeout(&mut self, buf: &mut [u8], timer: &MonoTimer, timeout: MilliSeconds) -> Result<(), UartError> {
+        let mut deadline = timer.deadline(timeout);
+        let mut len = 0;
+        while len < buf.len() {
+            match self.read() {
+                Ok(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) if deadline.expired() => return Err(UartError::Timeout),
+                Err(nb::Error::WouldBlock) => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a line ending in `\n` into `buf`, waiting at most `timeout` for
+    /// the whole line
+    ///
+    /// Returns the length of the line without the `\n` or `\r\n`
+    /// terminator. A line longer than `buf` is returned in pieces, each
+    /// filling `buf` completely. A piece ending in the `\r` of a `\r\n`
+    /// keeps it, and the following call returns `Ok(0)` for the `\n`.
+    pub fn read_line(&mut self, buf: &mut [u8], timer: &MonoTimer, timeout: MilliSeconds) -> Result<usize, UartError> {
+        let mut deadline = timer.deadline(timeout);
+        let mut len = 0;
+        while len < buf.len() {
+            match self.read() {
+                Ok(b'\n') => {
+                    if len > 0 && buf[len - 1] == b'\r' {
+                        len -= 1;
+                    }
+                    return Ok(len);
+                }
+                Ok(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) if deadline.expired() => return Err(UartError::Timeout),
+                Err(nb::Error::WouldBlock) => {},
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baudrate: Bps(115_200),
+            word_length: WordLength::DataBits8,
+            parity: Parity::None,
+            stop_bits: StopBits::Stop1,
+            irda: None,
+            invert_rx: false,
+            invert_tx: false,
+            msb_first: false,
+        }
+    }
 }
 
 impl Config {
     pub fn new(baudrate: BaudRate, parity: Parity, word_length: WordLength, stop_bits: StopBits) -> Config {
-        Config {baudrate, parity, word_length, stop_bits}
+        Config { baudrate: baudrate.into(), parity, word_length, stop_bits, ..Config::default() }
+    }
+
+    pub fn baudrate(mut self, baudrate: Bps) -> Self {
+        self.baudrate = baudrate;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn word_length(mut self, word_length: WordLength) -> Self {
+        self.word_length = word_length;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Inverts the polarity of the received data, idle low
+    pub fn invert_rx(mut self, invert: bool) -> Self {
+        self.invert_rx = invert;
+        self
+    }
+
+    /// Inverts the polarity of the transmitted data, idle low
+    pub fn invert_tx(mut self, invert: bool) -> Self {
+        self.invert_tx = invert;
+        self
+    }
+
+    /// Sends and receives the most significant bit first
+    pub fn msb_first(mut self, msb_first: bool) -> Self {
+        self.msb_first = msb_first;
+        self
+    }
+
+    /// Enables IrDA infrared encoding with the given transmit pulse width
+    pub fn irda(mut self, pulse_width: IrdaPulseWidth) -> Self {
+        self.irda = Some(pulse_width);
+        self
     }
 }
 
 trait ConfigMethod {
-    fn configure(&self, config: &Config, sim: &SIM);
+    fn configure(&self, config: &Config);
+
+    fn apply(&self, config: &Config);
 
-    fn get_real_baudrate(baudrate: &BaudRate) -> u32;
+    fn get_real_baudrate(baudrate: &Bps, clock: Hertz) -> (u16, u8);
 }
 
 macro_rules! uart {
-    ($($UART:ident: ($uart:ident, $uarttx:ident, $uartrx:ident, $scgc:ident),)+) => {
+    ($($UART:ident: ($uart:ident, $uarttx:ident, $uartrx:ident, $clock:ident),)+) => {
         $(
             use crate::pac::$UART;
 
@@ -128,9 +493,9 @@ macro_rules! uart {
                     TXPIN: TxPin<$UART>,
                     RXPIN: RxPin<$UART>
             {
-                pub fn $uart(uart: $UART, pins: (TXPIN, RXPIN), config: &Config, sim: &SIM) -> Self {
-                    let serial = Serial { uart, pins };
-                    serial.configure(config, sim);
+                pub fn $uart(uart: $UART, pins: (TXPIN, RXPIN), config: &Config, clocks: &Clocks) -> Self {
+                    let serial = Serial { uart, pins, clock: clocks.$clock() };
+                    serial.configure(config);
                     serial
                 }
             }
@@ -139,10 +504,10 @@ macro_rules! uart {
                 where
                     TXPIN: TxPin<$UART>,
             {
-                pub fn $uarttx(uart: $UART, txpin: TXPIN, config: &Config, sim: &SIM) -> Self {
+                pub fn $uarttx(uart: $UART, txpin: TXPIN, config: &Config, clocks: &Clocks) -> Self {
                     let rxpin = ();
-                    let serial = Serial { uart, pins: (txpin, rxpin) };
-                    serial.configure(config, sim);
+                    let serial = Serial { uart, pins: (txpin, rxpin), clock: clocks.$clock() };
+                    serial.configure(config);
                     serial
                 }
             }
@@ -151,14 +516,201 @@ macro_rules! uart {
                 where
                     RXPIN: RxPin<$UART>
             {
-                pub fn $uartrx(uart: $UART, rxpin: RXPIN, config: &Config, sim: &SIM) -> Self {
+                pub fn $uartrx(uart: $UART, rxpin: RXPIN, config: &Config, clocks: &Clocks) -> Self {
                     let txpin = ();
-                    let serial = Serial { uart, pins: (txpin, rxpin)};
-                    serial.configure(config, sim);
+                    let serial = Serial { uart, pins: (txpin, rxpin), clock: clocks.$clock() };
+                    serial.configure(config);
                     serial
                 }
             }
 
+            impl<TXPIN> Serial<$UART, TXPIN, ()>
+                where
+                    TXPIN: HalfDuplexPin<$UART>,
+            {
+                /// Routes the receiver to the open-drain TX pin, starting in
+                /// receive direction
+                pub fn into_half_duplex(self) -> Serial<$UART, TXPIN, SingleWire> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    uart.c3.modify(|_, w| w.txdir().clear_bit());
+                    uart.c1.modify(|_, w| w.loops().set_bit().rsrc().set_bit());
+                    let (txpin, ()) = self.pins;
+                    Serial { uart: self.uart, pins: (txpin, SingleWire { _private: () }), clock: self.clock }
+                }
+            }
+
+            impl<TXPIN> Serial<$UART, TXPIN, SingleWire> {
+                /// Switches the shared pin between driving and listening to the
+                /// line. Flush before switching to `Receive` so the last frame is
+                /// not cut short.
+                pub fn set_direction(&mut self, direction: HalfDuplexDirection) {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    uart.c3.modify(|_, w| w.txdir().bit(direction == HalfDuplexDirection::Transmit));
+                }
+
+                pub fn into_tx_only(self) -> Serial<$UART, TXPIN, ()> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    uart.c1.modify(|_, w| w.loops().clear_bit().rsrc().clear_bit());
+                    uart.c3.modify(|_, w| w.txdir().clear_bit());
+                    let (txpin, _) = self.pins;
+                    Serial { uart: self.uart, pins: (txpin, ()), clock: self.clock }
+                }
+            }
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN> {
+                /// Enables the UART status interrupt for `event`
+                pub fn listen(&mut self, event: Event) {
+                    Self::set_interrupt(event, true)
+                }
+
+                pub fn unlisten(&mut self, event: Event) {
+                    Self::set_interrupt(event, false)
+                }
+
+                fn set_interrupt(event: Event, enabled: bool) {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    cortex_m::interrupt::free(|_| match event {
+                        Event::ReceiveDataFull => uart.c2.modify(|_, w| w.rie().bit(enabled)),
+                        Event::TransmitDataEmpty => uart.c2.modify(|_, w| w.tie().bit(enabled)),
+                        Event::TransmissionComplete => uart.c2.modify(|_, w| w.tcie().bit(enabled)),
+                        Event::Idle => uart.c2.modify(|_, w| w.ilie().bit(enabled)),
+                        Event::RxActiveEdge => uart.bdh.modify(|_, w| w.rxedgie().bit(enabled)),
+                    })
+                }
+            }
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN> {
+                /// Queues a break character, 13 bits long when `long` is set
+                pub fn send_break(&mut self, long: bool) -> nb::Result<(), Infallible> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    if !uart.s1.read().tdre().bit() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    // LBKDIF and RXEDGIF are cleared by writing one, keep them
+                    uart.s2.modify(|_, w| w.brk13().bit(long).lbkdif().clear_bit().rxedgif().clear_bit());
+                    uart.c2.modify(|_, w| w.sbk().set_bit());
+                    uart.c2.modify(|_, w| w.sbk().clear_bit());
+                    Ok(())
+                }
+
+                /// While enabled, a break sets the LIN break flag instead of
+                /// being received as a framing error
+                pub fn set_lin_break_detection(&mut self, enabled: bool) {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    // RXEDGIF is cleared by writing one, keep it
+                    uart.s2.modify(|_, w| {
+                        w.lbkde().bit(enabled)
+                            .lbkdif().set_bit()
+                            .rxedgif().clear_bit()
+                    });
+                }
+
+                /// Returns `true` if a LIN break was received and clears the flag
+                pub fn take_lin_break(&mut self) -> bool {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    let detected = uart.s2.read().lbkdif().bit();
+                    if detected {
+                        uart.s2.modify(|_, w| w.lbkdif().set_bit().rxedgif().clear_bit());
+                    }
+                    detected
+                }
+            }
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN> {
+                /// Applies a new configuration, waiting for the transmission in
+                /// progress to complete before disabling the UART
+                pub fn reconfigure(&mut self, config: &Config) {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    if uart.c2.read().te().bit() {
+                        while !uart.s1.read().tc().bit() {}
+                    }
+                    self.apply(config);
+                }
+            }
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: DedicatedRxPin<$UART>
+            {
+                /// Connects the transmitter output to the receiver inside the
+                /// UART, the TX and RX pins are not used while enabled
+                pub fn set_loopback(&mut self, enabled: bool) {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    uart.c1.modify(|_, w| w.loops().bit(enabled));
+                }
+
+                /// Sends `pattern` through the internal loopback and checks what
+                /// the receiver gets back. The previous loopback setting is
+                /// restored afterwards.
+                pub fn self_test(&mut self, pattern: &[u8]) -> SelfTestReport {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    let loops = uart.c1.read().loops().bit();
+                    while !uart.s1.read().tc().bit() {}
+                    self.set_loopback(true);
+                    // Drop anything received before the test
+                    let _ = uart.s1.read();
+                    let _ = uart.d.read();
+
+                    let mut report = SelfTestReport::default();
+                    for byte in pattern {
+                        while !uart.s1.read().tdre().bit() {}
+                        uart.d.write(|w| unsafe { w.bits(*byte) });
+                        report.sent += 1;
+
+                        let mut polls = 0;
+                        let status = loop {
+                            let status = uart.s1.read();
+                            if status.rdrf().bit() || status.or().bit() {
+                                break Some(status);
+                            }
+                            polls += 1;
+                            if polls == SELF_TEST_POLLS {
+                                break None;
+                            }
+                        };
+                        let status = match status {
+                            Some(status) => status,
+                            None => {
+                                report.timeout += 1;
+                                continue;
+                            }
+                        };
+                        // Reading D after S1 also clears the error flags
+                        let received = uart.d.read().bits();
+                        report.received += 1;
+                        if status.or().bit() {
+                            report.overrun += 1;
+                        } else if status.fe().bit() {
+                            report.framing += 1;
+                        } else if status.nf().bit() {
+                            report.noise += 1;
+                        } else if status.pf().bit() {
+                            report.parity += 1;
+                        } else if received != *byte {
+                            report.mismatch += 1;
+                        }
+                    }
+
+                    while !uart.s1.read().tc().bit() {}
+                    self.set_loopback(loops);
+                    report
+                }
+            }
+
+            impl<TXPIN, RXPIN> lin::LinBreak for Serial<$UART, TXPIN, RXPIN> {
+                fn send_break(&mut self) -> nb::Result<(), Infallible> {
+                    Serial::send_break(self, true)
+                }
+
+                fn set_break_detection(&mut self, enabled: bool) {
+                    self.set_lin_break_detection(enabled)
+                }
+
+                fn take_break(&mut self) -> bool {
+                    self.take_lin_break()
+                }
+            }
+
             impl core::fmt::Write for Tx<$UART>
                 where
                     Tx<$UART>: embedded_hal::serial::Write<u8>,
@@ -183,27 +735,137 @@ macro_rules! uart {
                 }
             }
 
+            impl Rx<$UART> {
+                pub fn error_counts(&self) -> ErrorCounts {
+                    Self::error_counters().snapshot()
+                }
+
+                pub fn reset_error_counts(&mut self) {
+                    Self::error_counters().reset()
+                }
+
+                fn error_counters() -> &'static ErrorCounters {
+                    static COUNTERS: ErrorCounters = ErrorCounters::new();
+                    &COUNTERS
+                }
+
+                /// Reads S1, then R8 and finally D, which also clears the error
+                /// flags
+                fn read_data() -> nb::Result<u16, UartError> {
+                    Self::receive(true)
+                }
+
+                /// Like `read_data`, but a byte received with an error is left
+                /// in D along with its flags, so the next `read_data` reports it
+                #[cfg(feature = "embedded-io")]
+                fn read_valid_data() -> nb::Result<u16, UartError> {
+                    Self::receive(false)
+                }
+
+                fn receive(take_errors: bool) -> nb::Result<u16, UartError> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    let status_register = uart.s1.read();
+                    if !status_register.rdrf().bit() && !status_register.or().bit() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    let failed = status_register.or().bit() || status_register.fe().bit()
+                        || status_register.nf().bit() || status_register.pf().bit();
+                    if failed && !take_errors {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    let c1 = uart.c1.read();
+                    let ninth_bit = nine_bit_data(c1.m().bit(), c1.pe().bit(), uart.c4.read().m10().bit())
+                        && uart.c3.read().r8().bit();
+                    let data = uart.d.read().bits();
+                    let error = if status_register.or().bit() {
+                        UartError::Overrun(data)
+                    } else if status_register.fe().bit() && data == 0 {
+                        UartError::Break
+                    } else if status_register.fe().bit() {
+                        UartError::Framing(data)
+                    } else if status_register.nf().bit() {
+                        UartError::Noise(data)
+                    } else if status_register.pf().bit() {
+                        UartError::Parity(data)
+                    } else {
+                        return Ok(data as u16 | (ninth_bit as u16) << 8);
+                    };
+                    Self::error_counters().record(&error);
+                    Err(nb::Error::Other(error))
+                }
+            }
+
             impl Read<u8> for Rx<$UART>
             {
                 type Error = UartError;
 
                 fn read(&mut self) -> nb::Result<u8, Self::Error>
                 {
-                    let status_register = unsafe { (&*$UART::ptr()).s1.read() };
-                    if status_register.or().bit() {
-                        Err(nb::Error::Other(UartError::Overrun))
-                    } else if status_register.fe().bit() {
-                        Err(nb::Error::Other(UartError::Framing))
-                    } else if status_register.nf().bit() {
-                        Err(nb::Error::Other(UartError::Noise))
-                    } else if status_register.pf().bit() {
-                        Err(nb::Error::Other(UartError::Parity))
-                    } else if status_register.rdrf().bit() {
-                        let d = unsafe {  (&*$UART::ptr())}.d.read();
-                        Ok(d.bits())
-                    } else {
-                        Err(nb::Error::WouldBlock)
+                    Self::read_data().map(|data| data as u8)
+                }
+            }
+
+            impl Read<u16> for Rx<$UART>
+            {
+                type Error = UartError;
+
+                fn read(&mut self) -> nb::Result<u16, Self::Error>
+                {
+                    Self::read_data()
+                }
+            }
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN> {
+                pub fn error_counts(&self) -> ErrorCounts {
+                    Rx::<$UART>::error_counters().snapshot()
+                }
+
+                pub fn reset_error_counts(&mut self) {
+                    Rx::<$UART>::error_counters().reset()
+                }
+            }
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: RxPin<$UART>
+            {
+                /// Measures the baud rate of a `0x55` sync character sent by
+                /// the peer and applies the nearest standard rate on top of
+                /// `config`
+                ///
+                /// The receiver is disabled while measuring, so the sync
+                /// character itself is not received.
+                pub fn auto_baud(&mut self, config: &Config, timer: &MonoTimer, timeout: MilliSeconds) -> Result<Bps, UartError> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    uart.c2.modify(|_, w| w.re().clear_bit());
+                    let mut deadline = timer.deadline(timeout);
+                    let mut first_edge = timer.now();
+                    let mut cycles = 0;
+                    for edge in 0..AUTO_BAUD_EDGES {
+                        // Clears RXEDGIF, and LBKDIF along with it
+                        uart.s2.modify(|_, w| w.rxedgif().set_bit());
+                        while !uart.s2.read().rxedgif().bit() {
+                            if deadline.expired() {
+                                uart.c2.modify(|_, w| w.re().set_bit());
+                                return Err(UartError::Timeout);
+                            }
+                        }
+                        if edge == 0 {
+                            first_edge = timer.now();
+                        } else {
+                            cycles = first_edge.elapsed();
+                        }
                     }
+                    uart.s2.modify(|_, w| w.rxedgif().set_bit());
+                    let cycles = cycles.max(1) as u64;
+                    let measured = Bps((timer.frequency().0 as u64 * AUTO_BAUD_BITS / cycles) as u32);
+                    let baudrate = nearest_standard_baudrate(measured);
+                    // Let the stop bit pass before enabling the receiver again
+                    let bit_ticks = cycles / AUTO_BAUD_BITS;
+                    let end = timer.now();
+                    while (end.elapsed() as u64) < 2 * bit_ticks {}
+                    self.reconfigure(&config.baudrate(baudrate));
+                    Ok(baudrate)
                 }
             }
 
@@ -215,41 +877,46 @@ macro_rules! uart {
 
                 fn read(&mut self) -> nb::Result<u8, Self::Error>
                 {
-                    let status_register = unsafe { (&*$UART::ptr()).s1.read() };
-                    if status_register.or().bit() {
-                        Err(nb::Error::Other(UartError::Overrun))
-                    } else if status_register.fe().bit() {
-                        Err(nb::Error::Other(UartError::Framing))
-                    } else if status_register.nf().bit() {
-                        Err(nb::Error::Other(UartError::Noise))
-                    } else if status_register.pf().bit() {
-                        Err(nb::Error::Other(UartError::Parity))
-                    } else if status_register.rdrf().bit() {
-                        let d = unsafe {  (&*$UART::ptr())}.d.read();
-                        Ok(d.bits())
-                    } else {
-                        Err(nb::Error::WouldBlock)
-                    }
+                    Rx::<$UART>::read_data().map(|data| data as u8)
                 }
             }
 
-            impl Write<u8> for Tx<$UART> {
-                type Error = Infallible;
+            impl<TXPIN, RXPIN> Read<u16> for Serial<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: RxPin<$UART>
+            {
+                type Error = UartError;
 
-                fn write(&mut self, data: u8) -> nb::Result<(), Self::Error>
+                fn read(&mut self) -> nb::Result<u16, Self::Error>
                 {
+                    Rx::<$UART>::read_data()
+                }
+            }
+
+            impl Tx<$UART> {
+                /// Writes T8 before D, the ninth bit is only accepted when the
+                /// frame carries nine data bits
+                fn write_data(data: u16) -> nb::Result<(), WordLengthError> {
                     let uart = unsafe { (&*$UART::ptr())};
+                    let nine_bit_data = nine_bit_data(uart.c1.read().m().bit(),
+                                                      uart.c1.read().pe().bit(),
+                                                      uart.c4.read().m10().bit());
+                    if data > 0x1FF || (data > 0xFF && !nine_bit_data) {
+                        return Err(nb::Error::Other(WordLengthError));
+                    }
                     let status_register = uart.s1.read();
                     if status_register.tdre().bit() {
-                        uart.d.write(|w| unsafe { w.bits(data) });
+                        if nine_bit_data {
+                            uart.c3.modify(|_, w| w.t8().bit(data & 0x100 != 0));
+                        }
+                        uart.d.write(|w| unsafe { w.bits(data as u8) });
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
                     }
                 }
 
-                fn flush(&mut self) -> nb::Result<(), Self::Error>
-                {
+                fn flush_data() -> nb::Result<(), Infallible> {
                     let status_register = unsafe { (&*$UART::ptr()) }.s1.read();
                     if status_register.tc().bit() {
                         Ok(())
@@ -259,6 +926,34 @@ macro_rules! uart {
                 }
             }
 
+            impl Write<u8> for Tx<$UART> {
+                type Error = Infallible;
+
+                fn write(&mut self, data: u8) -> nb::Result<(), Self::Error>
+                {
+                    Self::write_data(data as u16).map_err(|e| e.map(|_| unreachable!()))
+                }
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error>
+                {
+                    Self::flush_data()
+                }
+            }
+
+            impl Write<u16> for Tx<$UART> {
+                type Error = WordLengthError;
+
+                fn write(&mut self, data: u16) -> nb::Result<(), Self::Error>
+                {
+                    Self::write_data(data)
+                }
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error>
+                {
+                    Self::flush_data().map_err(|e| e.map(|e| match e {}))
+                }
+            }
+
             impl<TXPIN, RXPIN> Write<u8> for Serial<$UART, TXPIN, RXPIN>
                 where
                     TXPIN: TxPin<$UART>
@@ -267,51 +962,91 @@ macro_rules! uart {
 
                 fn write(&mut self, data: u8) -> nb::Result<(), Self::Error>
                 {
-                    let uart = unsafe { (&*$UART::ptr())};
-                    let status_register = uart.s1.read();
-                    if status_register.tdre().bit() {
-                        uart.d.write(|w| unsafe { w.bits(data) });
-                        Ok(())
-                    } else {
-                        Err(nb::Error::WouldBlock)
-                    }
+                    Tx::<$UART>::write_data(data as u16).map_err(|e| e.map(|_| unreachable!()))
                 }
 
                 fn flush(&mut self) -> nb::Result<(), Self::Error>
                 {
-                    let status_register = unsafe { (&*$UART::ptr()) }.s1.read();
-                    if status_register.tc().bit() {
-                        Ok(())
-                    } else {
-                        Err(nb::Error::WouldBlock)
-                    }
+                    Tx::<$UART>::flush_data()
+                }
+            }
+
+            impl<TXPIN, RXPIN> Write<u16> for Serial<$UART, TXPIN, RXPIN>
+                where
+                    TXPIN: TxPin<$UART>
+            {
+                type Error = WordLengthError;
+
+                fn write(&mut self, data: u16) -> nb::Result<(), Self::Error>
+                {
+                    Tx::<$UART>::write_data(data)
+                }
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error>
+                {
+                    Tx::<$UART>::flush_data().map_err(|e| e.map(|e| match e {}))
                 }
             }
 
             impl<TXPIN, RXPIN> ConfigMethod for Serial<$UART, TXPIN, RXPIN> {
-                fn configure(&self, config: &Config, sim: &SIM) {
-                    sim.$scgc.modify(|_, w| w.$uart().set_bit());
-                    let uart = unsafe { (&*UART0::ptr())};
+                fn configure(&self, config: &Config) {
+                    $UART::enable();
+                    self.apply(config);
+                }
+
+                fn apply(&self, config: &Config) {
+                    let uart = unsafe { (&*$UART::ptr())};
                     uart.c2.modify(|_, w| w.te().clear_bit().re().clear_bit());
-                    let baudrate = Self::get_real_baudrate(&config.baudrate);
-                    let baudrate_high = ((baudrate & 0x1F00) >> 8) as u8;
-                    let baudrate_low = (baudrate & 0xFF) as u8;
+                    let (sbr, brfa) = Self::get_real_baudrate(&config.baudrate, self.clock);
+                    let baudrate_high = ((sbr & 0x1F00) >> 8) as u8;
+                    let baudrate_low = (sbr & 0xFF) as u8;
                     uart.bdh.modify(|_, w| unsafe {
                         w.sbr().bits(baudrate_high)
                             .sbns().bit(config.stop_bits == StopBits::Stop2)
                     });
                     uart.bdl.modify(|_, w| unsafe { w.sbr().bits(baudrate_low) });
+                    // The parity bit takes the place of the last data bit, so
+                    // eight data bits with parity need the 9 bit frame and
+                    // nine data bits with parity need the 10 bit one
+                    let is_nine_bit = config.word_length == WordLength::DataBits9;
+                    let has_parity = config.parity != Parity::None;
                     uart.c1.modify(|_, w| {
-                        let is_nine_bit = config.word_length == WordLength::DataBits9;
-                        w.pe().bit(config.parity != Parity::None)
+                        w.pe().bit(has_parity)
                             .pt().bit(config.parity == Parity::Odd)
-                            .m().bit(is_nine_bit)
+                            .m().bit(is_nine_bit || has_parity)
+                    });
+                    uart.c4.modify(|_, w| unsafe {
+                        w.m10().bit(is_nine_bit && has_parity)
+                            .brfa().bits(brfa)
+                    });
+                    // LBKDIF and RXEDGIF are cleared by writing one, keep them
+                    uart.s2.modify(|_, w| {
+                        w.msbf().bit(config.msb_first)
+                            .rxinv().bit(config.invert_rx)
+                            .lbkdif().clear_bit()
+                            .rxedgif().clear_bit()
+                    });
+                    uart.c3.modify(|_, w| w.txinv().bit(config.invert_tx));
+                    let pulse_width = match config.irda {
+                        Some(IrdaPulseWidth::ThreeSixteenths) | None => 0b00,
+                        Some(IrdaPulseWidth::OneSixteenth) => 0b01,
+                        Some(IrdaPulseWidth::OneThirtySecond) => 0b10,
+                        Some(IrdaPulseWidth::OneQuarter) => 0b11,
+                    };
+                    uart.ir.write(|w| unsafe {
+                        w.iren().bit(config.irda.is_some())
+                            .tnp().bits(pulse_width)
                     });
                     uart.c2.modify(|_, w| w.te().set_bit().re().set_bit());
                 }
 
-                fn get_real_baudrate(baudrate: &BaudRate) -> u32 {
-                    (20_971_520 / (baudrate.0 * 16)) + 1
+                /// Splits `clock / (16 * baudrate)` into the 13 bit SBR and the
+                /// fine adjust BRFA in 1/32 steps, rounded to nearest
+                fn get_real_baudrate(baudrate: &Bps, clock: Hertz) -> (u16, u8) {
+                    let div32 = (clock.0 as u64 * 2 + baudrate.0 as u64 / 2) / baudrate.0 as u64;
+                    let sbr = (div32 / 32).clamp(1, 0x1FFF) as u16;
+                    let brfa = if div32 / 32 > 0x1FFF { 31 } else { (div32 % 32) as u8 };
+                    (sbr, brfa)
                 }
             }
         )+
@@ -319,5 +1054,10 @@ macro_rules! uart {
 }
 
 uart! {
-    UART0: (uart0, uart0tx, uart0rx, scgc4),
+    UART0: (uart0, uart0tx, uart0rx, core),
+    UART1: (uart1, uart1tx, uart1rx, core),
+    UART2: (uart2, uart2tx, uart2rx, bus),
+    UART3: (uart3, uart3tx, uart3rx, bus),
+    UART4: (uart4, uart4tx, uart4rx, bus),
+    UART5: (uart5, uart5tx, uart5rx, bus),
 }
\ No newline at end of file
@@ -0,0 +1,272 @@
+//! Async serial transmitter and receiver
+//!
+//! The futures enable the status interrupt they wait for and go to sleep.
+//! Call `on_interrupt::<UARTx>()` from the `UARTx_RX_TX` handler and unmask
+//! that interrupt in the NVIC; the handler disables the interrupt sources
+//! that fired and wakes the waiting task.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+use cortex_m::interrupt::{self, Mutex};
+use embedded_io_async::{ErrorType, Read, Write};
+use super::{Rx, Tx, UartError};
+
+struct WakerSlot {
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl WakerSlot {
+    const fn new() -> Self {
+        WakerSlot { waker: Mutex::new(RefCell::new(None)) }
+    }
+
+    fn register(&self, waker: &Waker) {
+        interrupt::free(|cs| {
+            let mut slot = self.waker.borrow(cs).borrow_mut();
+            match &*slot {
+                Some(registered) if registered.will_wake(waker) => {},
+                _ => *slot = Some(waker.clone()),
+            }
+        })
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = interrupt::free(|cs| self.waker.borrow(cs).borrow_mut().take()) {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Wakers {
+    rx: WakerSlot,
+    tx: WakerSlot,
+}
+
+impl Wakers {
+    const fn new() -> Self {
+        Wakers { rx: WakerSlot::new(), tx: WakerSlot::new() }
+    }
+}
+
+/// UART instances usable with the async types
+pub trait Instance {
+    #[doc(hidden)]
+    fn wakers() -> &'static Wakers;
+
+    #[doc(hidden)]
+    fn handle_interrupt(wakers: &Wakers);
+}
+
+/// Services the UART status interrupt, to be called from its handler
+pub fn on_interrupt<UART: Instance>() {
+    UART::handle_interrupt(UART::wakers())
+}
+
+pub struct AsyncTx<UART> {
+    tx: Tx<UART>,
+}
+
+pub struct AsyncRx<UART> {
+    rx: Rx<UART>,
+}
+
+impl<UART: Instance> Tx<UART> {
+    pub fn into_async(self) -> AsyncTx<UART> {
+        AsyncTx { tx: self }
+    }
+}
+
+impl<UART: Instance> Rx<UART> {
+    pub fn into_async(self) -> AsyncRx<UART> {
+        AsyncRx { rx: self }
+    }
+}
+
+impl<UART> AsyncTx<UART> {
+    pub fn free(self) -> Tx<UART> {
+        self.tx
+    }
+}
+
+impl<UART> AsyncRx<UART> {
+    pub fn free(self) -> Rx<UART> {
+        self.rx
+    }
+}
+
+impl<UART> ErrorType for AsyncTx<UART> {
+    type Error = Infallible;
+}
+
+impl<UART> ErrorType for AsyncRx<UART> {
+    type Error = UartError;
+}
+
+macro_rules! asynch {
+    ($($UART:ident,)+) => {
+        $(
+            use crate::pac::$UART;
+
+            impl Instance for $UART {
+                fn wakers() -> &'static Wakers {
+                    static WAKERS: Wakers = Wakers::new();
+                    &WAKERS
+                }
+
+                fn handle_interrupt(wakers: &Wakers) {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    let status_register = uart.s1.read();
+                    let control = uart.c2.read();
+                    let received = status_register.rdrf().bit() || status_register.or().bit();
+                    if (control.rie().bit() && received)
+                        || (control.ilie().bit() && status_register.idle().bit())
+                    {
+                        uart.c2.modify(|_, w| w.rie().clear_bit().ilie().clear_bit());
+                        wakers.rx.wake();
+                    }
+                    if (control.tie().bit() && status_register.tdre().bit())
+                        || (control.tcie().bit() && status_register.tc().bit())
+                    {
+                        uart.c2.modify(|_, w| w.tie().clear_bit().tcie().clear_bit());
+                        wakers.tx.wake();
+                    }
+                }
+            }
+
+            impl AsyncRx<$UART> {
+                /// Receives into `buf` until the line goes idle after at least
+                /// one byte, or `buf` is full. A byte received with an error
+                /// ends it early, the next call reports the error.
+                pub async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, UartError> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    let mut len = 0;
+                    poll_fn(|cx| {
+                        loop {
+                            while len < buf.len() {
+                                // An error after the first byte is left for the
+                                // next call, so the bytes before it are kept
+                                let data = if len == 0 {
+                                    Rx::<$UART>::read_data()
+                                } else {
+                                    Rx::<$UART>::read_valid_data()
+                                };
+                                match data {
+                                    Ok(data) => {
+                                        buf[len] = data as u8;
+                                        len += 1;
+                                    }
+                                    Err(nb::Error::Other(e)) => return Poll::Ready(Err(e)),
+                                    Err(nb::Error::WouldBlock) => break,
+                                }
+                            }
+                            if len == buf.len() {
+                                return Poll::Ready(Ok(len));
+                            }
+                            let status = uart.s1.read();
+                            if status.rdrf().bit() || status.or().bit() {
+                                let failed = status.or().bit() || status.fe().bit()
+                                    || status.nf().bit() || status.pf().bit();
+                                if failed && len > 0 {
+                                    return Poll::Ready(Ok(len));
+                                }
+                                // A byte completed since the last read, the
+                                // dummy read of D below would drop it
+                                continue;
+                            }
+                            // IDLE is cleared by reading S1 and then D
+                            if status.idle().bit() {
+                                let _ = uart.d.read();
+                                if len > 0 {
+                                    return Poll::Ready(Ok(len));
+                                }
+                            }
+                            break;
+                        }
+                        $UART::wakers().rx.register(cx.waker());
+                        interrupt::free(|_| uart.c2.modify(|_, w| w.rie().set_bit().ilie().set_bit()));
+                        Poll::Pending
+                    }).await
+                }
+            }
+
+            impl Read for AsyncRx<$UART> {
+                async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let uart = unsafe { (&*$UART::ptr())};
+                    poll_fn(|cx| {
+                        let mut len = 0;
+                        while len < buf.len() {
+                            // An error after the first byte is left for the
+                            // next read, so the bytes before it are kept
+                            let data = if len == 0 {
+                                Rx::<$UART>::read_data()
+                            } else {
+                                Rx::<$UART>::read_valid_data()
+                            };
+                            match data {
+                                Ok(data) => {
+                                    buf[len] = data as u8;
+                                    len += 1;
+                                }
+                                Err(nb::Error::Other(e)) => return Poll::Ready(Err(e)),
+                                Err(nb::Error::WouldBlock) => break,
+                            }
+                        }
+                        if len > 0 {
+                            return Poll::Ready(Ok(len));
+                        }
+                        $UART::wakers().rx.register(cx.waker());
+                        interrupt::free(|_| uart.c2.modify(|_, w| w.rie().set_bit()));
+                        Poll::Pending
+                    }).await
+                }
+            }
+
+            impl Write for AsyncTx<$UART> {
+                async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let uart = unsafe { (&*$UART::ptr())};
+                    poll_fn(|cx| {
+                        let mut len = 0;
+                        while len < buf.len() && Tx::<$UART>::write_data(buf[len] as u16).is_ok() {
+                            len += 1;
+                        }
+                        if len > 0 {
+                            return Poll::Ready(Ok(len));
+                        }
+                        $UART::wakers().tx.register(cx.waker());
+                        interrupt::free(|_| uart.c2.modify(|_, w| w.tie().set_bit()));
+                        Poll::Pending
+                    }).await
+                }
+
+                async fn flush(&mut self) -> Result<(), Self::Error> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    poll_fn(|cx| {
+                        if uart.s1.read().tc().bit() {
+                            return Poll::Ready(Ok(()));
+                        }
+                        $UART::wakers().tx.register(cx.waker());
+                        interrupt::free(|_| uart.c2.modify(|_, w| w.tcie().set_bit()));
+                        Poll::Pending
+                    }).await
+                }
+            }
+        )+
+    }
+}
+
+asynch! {
+    UART0,
+    UART1,
+    UART2,
+    UART3,
+    UART4,
+    UART5,
+}
@@ -0,0 +1,74 @@
+//! embedded-hal-nb 1.0 serial traits, on top of the embedded-hal 0.2 ones
+
+use core::convert::Infallible;
+use embedded_hal::serial as hal02;
+use embedded_hal_nb::serial::{Error, ErrorKind, ErrorType, Read, Write};
+use super::{Rx, Serial, Tx, UartError};
+
+impl Error for UartError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            UartError::Framing(_) | UartError::Break => ErrorKind::FrameFormat,
+            UartError::Noise(_) => ErrorKind::Noise,
+            UartError::Overrun(_) => ErrorKind::Overrun,
+            UartError::Parity(_) => ErrorKind::Parity,
+            UartError::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+impl<UART> ErrorType for Rx<UART> {
+    type Error = UartError;
+}
+
+impl<UART> Read<u8> for Rx<UART>
+    where
+        Rx<UART>: hal02::Read<u8, Error = UartError>
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        hal02::Read::read(self)
+    }
+}
+
+impl<UART> ErrorType for Tx<UART> {
+    type Error = Infallible;
+}
+
+impl<UART> Write<u8> for Tx<UART>
+    where
+        Tx<UART>: hal02::Write<u8, Error = Infallible>
+{
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        hal02::Write::write(self, word)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        hal02::Write::<u8>::flush(self)
+    }
+}
+
+impl<UART, TXPIN, RXPIN> ErrorType for Serial<UART, TXPIN, RXPIN> {
+    type Error = UartError;
+}
+
+impl<UART, TXPIN, RXPIN> Read<u8> for Serial<UART, TXPIN, RXPIN>
+    where
+        Serial<UART, TXPIN, RXPIN>: hal02::Read<u8, Error = UartError>
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        hal02::Read::read(self)
+    }
+}
+
+impl<UART, TXPIN, RXPIN> Write<u8> for Serial<UART, TXPIN, RXPIN>
+    where
+        Serial<UART, TXPIN, RXPIN>: hal02::Write<u8, Error = Infallible>
+{
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        hal02::Write::write(self, word).map_err(|e| e.map(|e| match e {}))
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        hal02::Write::<u8>::flush(self).map_err(|e| e.map(|e| match e {}))
+    }
+}
@@ -0,0 +1,159 @@
+//! `embedded-io` traits for the serial types
+//!
+//! `read` blocks until at least one byte is available and then returns what
+//! the receiver already holds, `write` blocks until at least one byte fits.
+
+use core::convert::Infallible;
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write, WriteReady};
+use super::{RxPin, Rx, Serial, Tx, TxPin, UartError};
+
+impl embedded_io::Error for UartError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            UartError::Framing(_) | UartError::Noise(_) | UartError::Parity(_) => ErrorKind::InvalidData,
+            UartError::Overrun(_) | UartError::Break => ErrorKind::Other,
+            UartError::Timeout => ErrorKind::TimedOut,
+        }
+    }
+}
+
+/// Fills `buf` with the first byte received and whatever follows without
+/// waiting. The read ends early before a byte received with an error, which
+/// is left for the next read to report.
+fn read_available(
+    buf: &mut [u8],
+    read_data: fn() -> nb::Result<u16, UartError>,
+    read_valid_data: fn() -> nb::Result<u16, UartError>,
+) -> Result<usize, UartError> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    buf[0] = nb::block!(read_data())? as u8;
+    let mut len = 1;
+    while len < buf.len() {
+        match read_valid_data() {
+            Ok(data) => {
+                buf[len] = data as u8;
+                len += 1;
+            }
+            Err(_) => break,
+        }
+    }
+    Ok(len)
+}
+
+/// Writes the first byte of `buf` and whatever else fits without waiting
+fn write_available<W>(writer: &mut W, buf: &[u8]) -> usize
+    where
+        W: embedded_hal::serial::Write<u8, Error = Infallible>
+{
+    if buf.is_empty() {
+        return 0;
+    }
+    nb::block!(writer.write(buf[0])).ok();
+    let mut len = 1;
+    while len < buf.len() && writer.write(buf[len]).is_ok() {
+        len += 1;
+    }
+    len
+}
+
+macro_rules! io {
+    ($($UART:ident,)+) => {
+        $(
+            use crate::pac::$UART;
+
+            impl ErrorType for Rx<$UART> {
+                type Error = UartError;
+            }
+
+            impl Read for Rx<$UART> {
+                fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                    read_available(buf, Rx::<$UART>::read_data, Rx::<$UART>::read_valid_data)
+                }
+            }
+
+            impl ReadReady for Rx<$UART> {
+                fn read_ready(&mut self) -> Result<bool, Self::Error> {
+                    let status_register = unsafe { (&*$UART::ptr()) }.s1.read();
+                    Ok(status_register.rdrf().bit() || status_register.or().bit())
+                }
+            }
+
+            impl ErrorType for Tx<$UART> {
+                type Error = Infallible;
+            }
+
+            impl Write for Tx<$UART> {
+                fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                    Ok(write_available(self, buf))
+                }
+
+                fn flush(&mut self) -> Result<(), Self::Error> {
+                    nb::block!(embedded_hal::serial::Write::<u8>::flush(self))
+                }
+            }
+
+            impl WriteReady for Tx<$UART> {
+                fn write_ready(&mut self) -> Result<bool, Self::Error> {
+                    Ok(unsafe { (&*$UART::ptr()) }.s1.read().tdre().bit())
+                }
+            }
+
+            impl<TXPIN, RXPIN> ErrorType for Serial<$UART, TXPIN, RXPIN> {
+                type Error = UartError;
+            }
+
+            impl<TXPIN, RXPIN> Read for Serial<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: RxPin<$UART>
+            {
+                fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                    read_available(buf, Rx::<$UART>::read_data, Rx::<$UART>::read_valid_data)
+                }
+            }
+
+            impl<TXPIN, RXPIN> ReadReady for Serial<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: RxPin<$UART>
+            {
+                fn read_ready(&mut self) -> Result<bool, Self::Error> {
+                    let status_register = unsafe { (&*$UART::ptr()) }.s1.read();
+                    Ok(status_register.rdrf().bit() || status_register.or().bit())
+                }
+            }
+
+            impl<TXPIN, RXPIN> Write for Serial<$UART, TXPIN, RXPIN>
+                where
+                    TXPIN: TxPin<$UART>
+            {
+                fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                    Ok(write_available(self, buf))
+                }
+
+                fn flush(&mut self) -> Result<(), Self::Error> {
+                    nb::block!(embedded_hal::serial::Write::<u8>::flush(self)).ok();
+                    Ok(())
+                }
+            }
+
+            impl<TXPIN, RXPIN> WriteReady for Serial<$UART, TXPIN, RXPIN>
+                where
+                    TXPIN: TxPin<$UART>
+            {
+                fn write_ready(&mut self) -> Result<bool, Self::Error> {
+                    Ok(unsafe { (&*$UART::ptr()) }.s1.read().tdre().bit())
+                }
+            }
+        )+
+    }
+}
+
+io! {
+    UART0,
+    UART1,
+    UART2,
+    UART3,
+    UART4,
+    UART5,
+}
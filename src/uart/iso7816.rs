@@ -0,0 +1,314 @@
+//! ISO-7816 smart card interface on UART0
+//!
+//! The `Serial` must be configured for the elementary time unit of the card,
+//! e.g. 9600 bps for a 3.5712 MHz card clock with the default F = 372 and
+//! D = 1. Character framing (8 data bits, even parity) and the T=0 error
+//! signalling are handled by the ISO-7816 block.
+
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+use crate::pac::UART0;
+use super::{RxPin, Serial, TxPin, UartError};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    /// Character oriented, with NACK and retransmission on parity errors
+    T0,
+    /// Block oriented, with character and block waiting times
+    T1,
+}
+
+#[derive(Debug)]
+pub enum SmartCardError {
+    Uart(UartError),
+    /// The card did not answer with an initial character
+    NoAtr,
+    /// A character was NACKed more often than the transmit threshold
+    TransmitRetries,
+    /// More parity errors than the receive threshold were NACKed
+    ReceiveRetries,
+    /// T=0 work waiting time elapsed
+    WaitTime,
+    /// T=1 character waiting time elapsed
+    CharacterWaitTime,
+    /// T=1 block waiting time elapsed
+    BlockWaitTime,
+    /// The card did not respect the guard time
+    GuardTime,
+    /// T=1 block does not fit in the buffer
+    BlockLength,
+}
+
+impl From<UartError> for SmartCardError {
+    fn from(error: UartError) -> Self {
+        SmartCardError::Uart(error)
+    }
+}
+
+pub struct SmartCardConfig {
+    protocol: Protocol,
+    guard_time: u8,
+    wait_fd: u8,
+    wait_integer: u8,
+    block_wait_integer: u8,
+    character_wait_integer: u8,
+    tx_retries: u8,
+    rx_retries: u8,
+}
+
+impl Default for SmartCardConfig {
+    fn default() -> Self {
+        SmartCardConfig {
+            protocol: Protocol::T0,
+            guard_time: 0,
+            wait_fd: 1,
+            wait_integer: 10,
+            block_wait_integer: 4,
+            character_wait_integer: 13,
+            tx_retries: 3,
+            rx_retries: 3,
+        }
+    }
+}
+
+impl SmartCardConfig {
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Extra guard time in ETUs, the `N` value from the ATR
+    pub fn guard_time(mut self, etus: u8) -> Self {
+        self.guard_time = etus;
+        self
+    }
+
+    /// Waiting time scaling, `D` in T=0 and the BWT adjustment in T=1
+    pub fn wait_fd(mut self, fd: u8) -> Self {
+        self.wait_fd = fd;
+        self
+    }
+
+    /// T=0 work waiting integer `WI`
+    pub fn wait_integer(mut self, wi: u8) -> Self {
+        self.wait_integer = wi;
+        self
+    }
+
+    /// T=1 block waiting integer `BWI`, 0 to 15
+    pub fn block_wait_integer(mut self, bwi: u8) -> Self {
+        self.block_wait_integer = bwi & 0x0F;
+        self
+    }
+
+    /// T=1 character waiting integer `CWI`, 0 to 15
+    pub fn character_wait_integer(mut self, cwi: u8) -> Self {
+        self.character_wait_integer = cwi & 0x0F;
+        self
+    }
+
+    /// NACKs received for one character before `TransmitRetries`, 0 to 15
+    pub fn tx_retries(mut self, retries: u8) -> Self {
+        self.tx_retries = retries & 0x0F;
+        self
+    }
+
+    /// Parity errors NACKed before `ReceiveRetries`, 0 to 15
+    pub fn rx_retries(mut self, retries: u8) -> Self {
+        self.rx_retries = retries & 0x0F;
+        self
+    }
+}
+
+pub struct SmartCard<TXPIN, RXPIN> {
+    serial: Serial<UART0, TXPIN, RXPIN>,
+    protocol: Protocol,
+}
+
+impl<TXPIN, RXPIN> SmartCard<TXPIN, RXPIN>
+    where
+        TXPIN: TxPin<UART0>,
+        RXPIN: RxPin<UART0>
+{
+    pub fn new(serial: Serial<UART0, TXPIN, RXPIN>, config: &SmartCardConfig) -> Self {
+        let uart = unsafe { (&*UART0::ptr())};
+        uart.c2.modify(|_, w| w.te().clear_bit().re().clear_bit());
+        uart.c1.modify(|_, w| w.m().set_bit().pe().set_bit().pt().clear_bit());
+        uart.bdh.modify(|_, w| w.sbns().clear_bit());
+        uart.c4.modify(|_, w| w.m10().clear_bit());
+        uart.wp7816t0.write(|w| unsafe { w.wi().bits(config.wait_integer) });
+        if config.protocol == Protocol::T1 {
+            uart.wp7816t1.write(|w| unsafe {
+                w.bwi().bits(config.block_wait_integer)
+                    .cwi().bits(config.character_wait_integer)
+            });
+        }
+        uart.wn7816.write(|w| unsafe { w.gtn().bits(config.guard_time) });
+        uart.wf7816.write(|w| unsafe { w.gtfd().bits(config.wait_fd) });
+        uart.et7816.write(|w| unsafe {
+            w.txthreshold().bits(config.tx_retries)
+                .rxthreshold().bits(config.rx_retries)
+        });
+        uart.is7816.write(|w| unsafe { w.bits(0xFF) });
+        uart.c7816.write(|w| {
+            w.iso_7816e().set_bit()
+                .ttype().bit(config.protocol == Protocol::T1)
+                .anack().bit(config.protocol == Protocol::T0)
+                .onack().clear_bit()
+        });
+        uart.c2.modify(|_, w| w.te().set_bit().re().set_bit());
+        SmartCard { serial, protocol: config.protocol }
+    }
+
+    /// Receives the answer to reset into `buf`
+    ///
+    /// Call right after releasing the card reset line. The initial character
+    /// selects direct or inverse convention, the answer ends when the waiting
+    /// time elapses or `buf` is full. Returns the number of bytes received.
+    ///
+    /// The answer is character framed whatever the protocol, it is received
+    /// in T=0 mode and T=1 is only selected afterwards.
+    pub fn receive_atr(&mut self, buf: &mut [u8]) -> Result<usize, SmartCardError> {
+        let uart = unsafe { (&*UART0::ptr())};
+        let protocol = self.protocol;
+        self.protocol = Protocol::T0;
+        uart.c7816.modify(|_, w| w.ttype().clear_bit());
+        let result = self.receive_characters(buf);
+        self.protocol = protocol;
+        uart.c7816.modify(|_, w| w.ttype().bit(protocol == Protocol::T1));
+        uart.is7816.write(|w| unsafe { w.bits(0xFF) });
+        result
+    }
+
+    fn receive_characters(&mut self, buf: &mut [u8]) -> Result<usize, SmartCardError> {
+        let uart = unsafe { (&*UART0::ptr())};
+        uart.is7816.write(|w| unsafe { w.bits(0xFF) });
+        uart.c7816.modify(|_, w| w.init().set_bit());
+        loop {
+            let status = uart.is7816.read();
+            if status.initd().bit() {
+                break;
+            } else if status.wt().bit() {
+                uart.c7816.modify(|_, w| w.init().clear_bit());
+                return Err(SmartCardError::NoAtr);
+            }
+        }
+        let mut len = 0;
+        while len < buf.len() {
+            match self.read() {
+                Ok(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                Err(nb::Error::WouldBlock) => {},
+                Err(nb::Error::Other(SmartCardError::WaitTime)) if len > 0 => break,
+                Err(nb::Error::Other(e)) => return Err(e),
+            }
+        }
+        Ok(len)
+    }
+
+    /// Sends a T=1 block, prologue to epilogue
+    ///
+    /// Only blocks with the one byte LRC epilogue are supported, not CRC.
+    pub fn write_block(&mut self, block: &[u8]) -> Result<(), SmartCardError> {
+        if block.len() < 4 || block.len() != block[2] as usize + 4 {
+            return Err(SmartCardError::BlockLength);
+        }
+        let uart = unsafe { (&*UART0::ptr())};
+        uart.tl7816.write(|w| unsafe { w.tlen().bits(block[2]) });
+        for byte in block {
+            nb::block!(self.write(*byte))?;
+        }
+        Ok(())
+    }
+
+    /// Receives a T=1 block into `buf`, returning its length
+    ///
+    /// Like `write_block`, this expects the one byte LRC epilogue.
+    pub fn read_block(&mut self, buf: &mut [u8]) -> Result<usize, SmartCardError> {
+        if buf.len() < 4 {
+            return Err(SmartCardError::BlockLength);
+        }
+        for byte in buf[..3].iter_mut() {
+            *byte = nb::block!(self.read())?;
+        }
+        let len = buf[2] as usize + 4;
+        if len > buf.len() {
+            return Err(SmartCardError::BlockLength);
+        }
+        for byte in buf[3..len].iter_mut() {
+            *byte = nb::block!(self.read())?;
+        }
+        Ok(len)
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn free(self) -> Serial<UART0, TXPIN, RXPIN> {
+        let uart = unsafe { (&*UART0::ptr())};
+        uart.c7816.write(|w| w.iso_7816e().clear_bit());
+        self.serial
+    }
+
+    fn check_status(&self) -> Result<(), SmartCardError> {
+        let uart = unsafe { (&*UART0::ptr())};
+        let status = uart.is7816.read();
+        // The flags are cleared by writing one, only the reported one is
+        // cleared so the others show up on the next check
+        if status.txt().bit() {
+            uart.is7816.write(|w| w.txt().set_bit());
+            Err(SmartCardError::TransmitRetries)
+        } else if status.rxt().bit() {
+            uart.is7816.write(|w| w.rxt().set_bit());
+            Err(SmartCardError::ReceiveRetries)
+        } else if status.gtv().bit() {
+            uart.is7816.write(|w| w.gtv().set_bit());
+            Err(SmartCardError::GuardTime)
+        } else if status.wt().bit() && self.protocol == Protocol::T0 {
+            uart.is7816.write(|w| w.wt().set_bit());
+            Err(SmartCardError::WaitTime)
+        } else if status.cwt().bit() && self.protocol == Protocol::T1 {
+            uart.is7816.write(|w| w.cwt().set_bit());
+            Err(SmartCardError::CharacterWaitTime)
+        } else if status.bwt().bit() && self.protocol == Protocol::T1 {
+            uart.is7816.write(|w| w.bwt().set_bit());
+            Err(SmartCardError::BlockWaitTime)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<TXPIN, RXPIN> Read<u8> for SmartCard<TXPIN, RXPIN>
+    where
+        TXPIN: TxPin<UART0>,
+        RXPIN: RxPin<UART0>
+{
+    type Error = SmartCardError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.check_status()?;
+        self.serial.read().map_err(|e| e.map(SmartCardError::Uart))
+    }
+}
+
+impl<TXPIN, RXPIN> Write<u8> for SmartCard<TXPIN, RXPIN>
+    where
+        TXPIN: TxPin<UART0>,
+        RXPIN: RxPin<UART0>
+{
+    type Error = SmartCardError;
+
+    fn write(&mut self, data: u8) -> nb::Result<(), Self::Error> {
+        self.check_status()?;
+        self.serial.write(data).map_err(|e| e.map(|e: Infallible| match e {}))
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.check_status()?;
+        self.serial.flush().map_err(|e| e.map(|e: Infallible| match e {}))
+    }
+}
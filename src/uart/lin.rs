@@ -0,0 +1,296 @@
+//! LIN frame layer on top of `Serial`
+//!
+//! A LIN frame is a header sent by the master (break, sync byte `0x55` and
+//! protected identifier) followed by up to eight data bytes and a checksum,
+//! sent either by the master or by the addressed slave.
+
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+use crate::time::{MilliSeconds, MonoTimer};
+use super::UartError;
+
+/// Sync byte sent after the break
+pub const SYNC: u8 = 0x55;
+
+/// Largest data field of a LIN frame
+pub const MAX_DATA_LEN: usize = 8;
+
+/// Serial ports able to generate and detect LIN breaks
+pub trait LinBreak {
+    /// Queue a 13 bit break character
+    fn send_break(&mut self) -> nb::Result<(), Infallible>;
+
+    /// Enable or disable LIN break detection on the receiver
+    fn set_break_detection(&mut self, enabled: bool);
+
+    /// Returns `true` once a LIN break was received, clearing the flag
+    fn take_break(&mut self) -> bool;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumModel {
+    /// LIN 1.x, data bytes only
+    Classic,
+    /// LIN 2.x, data bytes and protected identifier
+    Enhanced,
+}
+
+#[derive(Debug)]
+pub enum LinError {
+    Uart(UartError),
+    /// Identifier does not fit in six bits
+    InvalidId,
+    /// Data field is empty or longer than eight bytes
+    InvalidLength,
+    /// Received protected identifier has wrong parity bits
+    Parity,
+    /// Received checksum does not match the data
+    Checksum,
+    /// The byte read back from the bus differs from the one sent
+    Readback,
+}
+
+impl From<UartError> for LinError {
+    fn from(error: UartError) -> Self {
+        LinError::Uart(error)
+    }
+}
+
+/// Adds the two parity bits to a six bit frame identifier
+pub fn protected_id(id: u8) -> Result<u8, LinError> {
+    if id > 0x3F {
+        return Err(LinError::InvalidId);
+    }
+    let bit = |n: u8| (id >> n) & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+    Ok(id | (p0 << 6) | (p1 << 7))
+}
+
+/// Strips and checks the parity bits of a received protected identifier
+pub fn frame_id(pid: u8) -> Result<u8, LinError> {
+    let id = pid & 0x3F;
+    if protected_id(id)? == pid {
+        Ok(id)
+    } else {
+        Err(LinError::Parity)
+    }
+}
+
+/// Computes the frame checksum
+///
+/// Diagnostic frames (`0x3C` and `0x3D`) always use the classic checksum, as
+/// required by LIN 2.x.
+pub fn checksum(model: ChecksumModel, pid: u8, data: &[u8]) -> u8 {
+    let id = pid & 0x3F;
+    let seed = match model {
+        ChecksumModel::Enhanced if id != 0x3C && id != 0x3D => pid as u16,
+        _ => 0,
+    };
+    let sum = data.iter().fold(seed, |sum, byte| {
+        let sum = sum + *byte as u16;
+        if sum > 0xFF { sum - 0xFF } else { sum }
+    });
+    !(sum as u8)
+}
+
+/// Encodes the part of a frame following the break into `buf`
+///
+/// Returns the number of bytes written: sync, protected identifier, data and
+/// checksum.
+pub fn encode_frame(model: ChecksumModel, id: u8, data: &[u8], buf: &mut [u8; MAX_DATA_LEN + 3]) -> Result<usize, LinError> {
+    if data.is_empty() || data.len() > MAX_DATA_LEN {
+        return Err(LinError::InvalidLength);
+    }
+    let pid = protected_id(id)?;
+    buf[0] = SYNC;
+    buf[1] = pid;
+    buf[2..2 + data.len()].copy_from_slice(data);
+    buf[2 + data.len()] = checksum(model, pid, data);
+    Ok(data.len() + 3)
+}
+
+/// LIN master node
+///
+/// The bus transceiver echoes every transmitted byte back to the receiver,
+/// the master reads the echo back and compares it with what was sent. A
+/// missing echo fails with `UartError::Timeout` after `echo_timeout`.
+pub struct LinMaster<SERIAL> {
+    serial: SERIAL,
+    model: ChecksumModel,
+    timer: MonoTimer,
+    echo_timeout: MilliSeconds,
+    pid: u8,
+    received: usize,
+    response: [u8; MAX_DATA_LEN + 1],
+}
+
+impl<SERIAL> LinMaster<SERIAL>
+    where
+        SERIAL: LinBreak + Read<u8, Error = UartError> + Write<u8, Error = Infallible>
+{
+    pub fn new(mut serial: SERIAL, model: ChecksumModel, timer: MonoTimer, echo_timeout: MilliSeconds) -> Self {
+        serial.set_break_detection(true);
+        LinMaster {
+            serial,
+            model,
+            timer,
+            echo_timeout,
+            pid: 0,
+            received: 0,
+            response: [0; MAX_DATA_LEN + 1],
+        }
+    }
+
+    /// Sends the frame header for `id`
+    pub fn send_header(&mut self, id: u8) -> Result<(), LinError> {
+        let pid = protected_id(id)?;
+        self.received = 0;
+        nb::block!(self.serial.send_break()).ok();
+        let mut deadline = self.timer.deadline(self.echo_timeout);
+        while !self.serial.take_break() {
+            if deadline.expired() {
+                return Err(LinError::Uart(UartError::Timeout));
+            }
+        }
+        self.write_checked(SYNC)?;
+        self.write_checked(pid)?;
+        self.pid = pid;
+        Ok(())
+    }
+
+    /// Sends a complete frame with the master providing the response
+    pub fn write_frame(&mut self, id: u8, data: &[u8]) -> Result<(), LinError> {
+        if data.is_empty() || data.len() > MAX_DATA_LEN {
+            return Err(LinError::InvalidLength);
+        }
+        self.send_header(id)?;
+        for byte in data {
+            self.write_checked(*byte)?;
+        }
+        self.write_checked(checksum(self.model, self.pid, data))
+    }
+
+    /// Collects the slave response to the last header into `buf`
+    ///
+    /// Returns `WouldBlock` until `buf.len()` data bytes and the checksum have
+    /// been received, so the caller decides how long to wait for a slave that
+    /// may never answer. A receive error drops the bytes collected so far.
+    pub fn read_response(&mut self, buf: &mut [u8]) -> nb::Result<usize, LinError> {
+        if buf.is_empty() || buf.len() > MAX_DATA_LEN {
+            return Err(nb::Error::Other(LinError::InvalidLength));
+        }
+        while self.received <= buf.len() {
+            match self.serial.read() {
+                Ok(byte) => {
+                    self.response[self.received] = byte;
+                    self.received += 1;
+                }
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                Err(nb::Error::Other(e)) => {
+                    self.received = 0;
+                    return Err(nb::Error::Other(LinError::Uart(e)));
+                }
+            }
+        }
+        let len = buf.len();
+        self.received = 0;
+        buf.copy_from_slice(&self.response[..len]);
+        if checksum(self.model, self.pid, buf) == self.response[len] {
+            Ok(len)
+        } else {
+            Err(nb::Error::Other(LinError::Checksum))
+        }
+    }
+
+    pub fn free(mut self) -> SERIAL {
+        self.serial.set_break_detection(false);
+        self.serial
+    }
+
+    fn write_checked(&mut self, byte: u8) -> Result<(), LinError> {
+        nb::block!(self.serial.write(byte)).ok();
+        let mut deadline = self.timer.deadline(self.echo_timeout);
+        let echo = loop {
+            match self.serial.read() {
+                Ok(echo) => break echo,
+                Err(nb::Error::Other(e)) => return Err(LinError::Uart(e)),
+                Err(nb::Error::WouldBlock) if deadline.expired() => {
+                    return Err(LinError::Uart(UartError::Timeout))
+                }
+                Err(nb::Error::WouldBlock) => {}
+            }
+        };
+        if echo == byte {
+            Ok(())
+        } else {
+            Err(LinError::Readback)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_id_parity() {
+        assert_eq!(protected_id(0x00).unwrap(), 0x80);
+        assert_eq!(protected_id(0x01).unwrap(), 0xC1);
+        assert_eq!(protected_id(0x10).unwrap(), 0x50);
+        assert_eq!(protected_id(0x3C).unwrap(), 0x3C);
+        assert_eq!(protected_id(0x3D).unwrap(), 0x7D);
+        assert_eq!(protected_id(0x3F).unwrap(), 0xBF);
+        assert!(matches!(protected_id(0x40), Err(LinError::InvalidId)));
+    }
+
+    #[test]
+    fn frame_id_checks_parity() {
+        assert_eq!(frame_id(0xC1).unwrap(), 0x01);
+        assert!(matches!(frame_id(0x41), Err(LinError::Parity)));
+    }
+
+    #[test]
+    fn classic_checksum() {
+        assert_eq!(checksum(ChecksumModel::Classic, 0x4A, &[0x55, 0x93, 0xE5]), 0x31);
+    }
+
+    #[test]
+    fn enhanced_checksum() {
+        assert_eq!(checksum(ChecksumModel::Enhanced, 0x4A, &[0x55, 0x93, 0xE5]), 0xE6);
+    }
+
+    #[test]
+    fn checksum_carry_wraps() {
+        assert_eq!(checksum(ChecksumModel::Classic, 0x00, &[0xFF, 0x01]), 0xFE);
+        assert_eq!(checksum(ChecksumModel::Classic, 0x00, &[0xFF, 0xFF]), 0x00);
+    }
+
+    #[test]
+    fn diagnostic_frames_use_classic_checksum() {
+        let data = [0x01, 0x02, 0x03];
+        for id in [0x3C, 0x3D].iter() {
+            let pid = protected_id(*id).unwrap();
+            assert_eq!(checksum(ChecksumModel::Enhanced, pid, &data),
+                       checksum(ChecksumModel::Classic, pid, &data));
+        }
+    }
+
+    #[test]
+    fn encode_frame_layout() {
+        let mut buf = [0; MAX_DATA_LEN + 3];
+        let len = encode_frame(ChecksumModel::Enhanced, 0x0A, &[0x55, 0x93, 0xE5], &mut buf).unwrap();
+        assert_eq!(&buf[..len], &[SYNC, 0xCA, 0x55, 0x93, 0xE5, 0x66]);
+    }
+
+    #[test]
+    fn encode_frame_rejects_invalid_input() {
+        let mut buf = [0; MAX_DATA_LEN + 3];
+        assert!(matches!(encode_frame(ChecksumModel::Classic, 0x01, &[], &mut buf),
+                         Err(LinError::InvalidLength)));
+        assert!(matches!(encode_frame(ChecksumModel::Classic, 0x01, &[0; MAX_DATA_LEN + 1], &mut buf),
+                         Err(LinError::InvalidLength)));
+        assert!(matches!(encode_frame(ChecksumModel::Classic, 0x40, &[0x00], &mut buf),
+                         Err(LinError::InvalidId)));
+    }
+}
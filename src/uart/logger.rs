@@ -0,0 +1,275 @@
+//! `log` and `defmt` backends writing through a `Tx`
+//!
+//! Log output goes into a buffer that is drained into the transmitter
+//! without waiting, after every log call and whenever `pump` is called,
+//! e.g. from the idle loop. A message or defmt frame that does not fit in
+//! the buffer is dropped whole and counted, logging never blocks.
+//!
+//! The defmt logger also needs the `defmt.x` linker script, and with the
+//! `defmt-timestamp` feature stamps frames with microseconds from the
+//! `MonoTimer` passed to `init`.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+use cortex_m::interrupt::{self, CriticalSection, Mutex};
+use embedded_hal::serial::Write;
+use crate::time::{Instant, MonoTimer};
+use super::Tx;
+
+/// Bytes of log output held while the transmitter is busy
+const BUFFER_SIZE: usize = 256;
+
+/// Writes one byte if the transmitter has room
+type Sink = fn(u8) -> bool;
+
+struct Clock {
+    timer: MonoTimer,
+    last: Instant,
+    ticks: u64,
+}
+
+impl Clock {
+    /// Microseconds since `init`, extending the 32 bit cycle counter. Needs
+    /// to be read at least once per counter period.
+    fn micros(&mut self) -> u64 {
+        let now = self.timer.now();
+        self.ticks += now.duration_since(self.last) as u64;
+        self.last = now;
+        // Split into seconds so the product cannot overflow
+        let frequency = self.timer.frequency().0 as u64;
+        self.ticks / frequency * 1_000_000 + self.ticks % frequency * 1_000_000 / frequency
+    }
+}
+
+struct State {
+    sink: Option<Sink>,
+    clock: Option<Clock>,
+    buffer: [u8; BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    /// Buffer length when the message being written was started
+    start: usize,
+    /// The message being written did not fit
+    overflow: bool,
+    dropped: u32,
+}
+
+impl State {
+    fn begin(&mut self) {
+        self.start = self.len;
+        self.overflow = false;
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.overflow || self.len + bytes.len() > BUFFER_SIZE {
+            self.overflow = true;
+            return;
+        }
+        for byte in bytes {
+            self.buffer[(self.head + self.len) % BUFFER_SIZE] = *byte;
+            self.len += 1;
+        }
+    }
+
+    /// Ends the message, removing it again if it did not fit
+    fn commit(&mut self) {
+        if self.overflow {
+            self.len = self.start;
+            self.overflow = false;
+            self.dropped += 1;
+        }
+        self.pump();
+    }
+
+    fn pump(&mut self) {
+        let sink = match self.sink {
+            Some(sink) => sink,
+            None => return,
+        };
+        while self.len > 0 && sink(self.buffer[self.head]) {
+            self.head = (self.head + 1) % BUFFER_SIZE;
+            self.len -= 1;
+        }
+    }
+}
+
+static STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State {
+    sink: None,
+    clock: None,
+    buffer: [0; BUFFER_SIZE],
+    head: 0,
+    len: 0,
+    start: 0,
+    overflow: false,
+    dropped: 0,
+}));
+
+fn sink<UART>(byte: u8) -> bool
+    where
+        Tx<UART>: Write<u8, Error = Infallible>
+{
+    Tx::<UART> { _instance: PhantomData }.write(byte).is_ok()
+}
+
+/// Hands `tx` over to the logging backends, with timestamps from `timer`
+/// when given
+pub fn init<UART>(tx: Tx<UART>, timer: Option<MonoTimer>)
+    where
+        Tx<UART>: Write<u8, Error = Infallible>
+{
+    drop(tx);
+    interrupt::free(|cs| {
+        let mut state = STATE.borrow(cs).borrow_mut();
+        state.sink = Some(sink::<UART>);
+        state.clock = timer.map(|timer| Clock { timer, last: timer.now(), ticks: 0 });
+    })
+}
+
+/// Moves buffered log output into the transmitter, as far as it has room
+pub fn pump() {
+    interrupt::free(|cs| STATE.borrow(cs).borrow_mut().pump())
+}
+
+/// Messages and defmt frames dropped because the buffer was full
+pub fn dropped() -> u32 {
+    interrupt::free(|cs| STATE.borrow(cs).borrow().dropped)
+}
+
+/// Starts a message, which is only pumped out once complete
+fn begin(cs: &CriticalSection) {
+    STATE.borrow(cs).borrow_mut().begin()
+}
+
+/// Adds to the message, all of it has to be written within the critical
+/// section it was started in
+fn write(cs: &CriticalSection, bytes: &[u8]) {
+    STATE.borrow(cs).borrow_mut().push(bytes)
+}
+
+fn commit(cs: &CriticalSection) {
+    STATE.borrow(cs).borrow_mut().commit()
+}
+
+#[cfg(feature = "log")]
+mod log_impl {
+    use core::fmt::{self, Write as _};
+    use cortex_m::interrupt::{self, CriticalSection};
+    use super::{begin, commit, write, STATE};
+
+    pub struct UartLogger;
+
+    pub static LOGGER: UartLogger = UartLogger;
+
+    struct Writer<'cs>(&'cs CriticalSection);
+
+    impl fmt::Write for Writer<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            write(self.0, s.as_bytes());
+            Ok(())
+        }
+    }
+
+    impl log::Log for UartLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &log::Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            interrupt::free(|cs| {
+                let micros = STATE.borrow(cs).borrow_mut().clock.as_mut().map(|clock| clock.micros());
+                begin(cs);
+                let mut writer = Writer(cs);
+                if let Some(micros) = micros {
+                    let _ = write!(writer, "[{}.{:06}] ", micros / 1_000_000, micros % 1_000_000);
+                }
+                let _ = write!(writer, "{:<5} {}\r\n", record.level(), record.args());
+                commit(cs);
+            })
+        }
+
+        fn flush(&self) {
+            super::pump()
+        }
+    }
+}
+
+/// Installs the `log` backend, call `init` first
+#[cfg(feature = "log")]
+pub fn init_log(level: log::LevelFilter) -> Result<(), log::SetLoggerError> {
+    log::set_logger(&log_impl::LOGGER).map(|()| log::set_max_level(level))
+}
+
+#[cfg(feature = "defmt")]
+mod defmt_impl {
+    use core::ptr::addr_of_mut;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use cortex_m::interrupt::CriticalSection;
+    use cortex_m::register::primask;
+    use super::{begin, commit, write};
+
+    #[defmt::global_logger]
+    struct DefmtLogger;
+
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    static mut INTERRUPTS_ACTIVE: bool = false;
+    static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+    /// Only called between `acquire` and `release`, with interrupts masked
+    fn critical_section() -> CriticalSection {
+        unsafe { CriticalSection::new() }
+    }
+
+    /// Only called between `acquire` and `release`, so there is no other
+    /// reference
+    unsafe fn encoder() -> &'static mut defmt::Encoder {
+        &mut *addr_of_mut!(ENCODER)
+    }
+
+    fn do_write(bytes: &[u8]) {
+        write(&critical_section(), bytes)
+    }
+
+    unsafe impl defmt::Logger for DefmtLogger {
+        fn acquire() {
+            let active = primask::read().is_active();
+            cortex_m::interrupt::disable();
+            if TAKEN.load(Ordering::Relaxed) {
+                panic!("defmt logger taken reentrantly")
+            }
+            TAKEN.store(true, Ordering::Relaxed);
+            unsafe {
+                INTERRUPTS_ACTIVE = active;
+                begin(&critical_section());
+                encoder().start_frame(do_write);
+            }
+        }
+
+        unsafe fn flush() {
+            super::pump()
+        }
+
+        unsafe fn release() {
+            encoder().end_frame(do_write);
+            commit(&critical_section());
+            TAKEN.store(false, Ordering::Relaxed);
+            if INTERRUPTS_ACTIVE {
+                cortex_m::interrupt::enable();
+            }
+        }
+
+        unsafe fn write(bytes: &[u8]) {
+            encoder().write(bytes, do_write);
+        }
+    }
+
+    #[cfg(feature = "defmt-timestamp")]
+    defmt::timestamp!("{=u64:us}", {
+        cortex_m::interrupt::free(|cs| {
+            super::STATE.borrow(cs).borrow_mut().clock.as_mut().map(|clock| clock.micros()).unwrap_or(0)
+        })
+    });
+}
@@ -0,0 +1,143 @@
+//! Waking the MCU from low-power modes on UART receive activity
+//!
+//! In VLPS the RX active edge interrupt wakes the core directly. In LLS the
+//! UART is not clocked and only the low-leakage wakeup unit can wake the MCU,
+//! which works for RX pins that double as LLWU inputs (`LlwuRxPin`).
+//!
+//! `Serial::arm_rx_wakeup` consumes the `Serial` and `RxWakeup::restore`
+//! gives it back, so the port cannot be used while armed. The character
+//! that woke the MCU is usually not received correctly and is discarded on
+//! restore.
+
+use crate::gpio::*;
+use crate::pac::LLWU;
+use super::{RxPin, Serial};
+
+/// RX pins that are also LLWU wakeup inputs
+pub trait LlwuRxPin<UART>: RxPin<UART> {
+    /// Index of the LLWU_Pn input
+    const LLWU_INPUT: u8;
+}
+
+macro_rules! llwu_rx_pins {
+    ($($UART:ident => [$($rx:ty: $input:expr),+ $(,)*],)+) => {
+        $(
+            $(
+                impl LlwuRxPin<crate::pac::$UART> for $rx {
+                    const LLWU_INPUT: u8 = $input;
+                }
+            )+
+        )+
+    }
+}
+
+llwu_rx_pins! {
+    UART0 => [portd::PD6<Alternative<AF3>>: 15],
+    UART1 => [portc::PC3<Alternative<AF3>>: 7, porte::PE1<Alternative<AF3>>: 0],
+    UART2 => [portd::PD2<Alternative<AF3>>: 13],
+}
+
+/// LLWU_PEn field values
+const LLWU_DISABLED: u8 = 0b00;
+const LLWU_RISING: u8 = 0b01;
+const LLWU_FALLING: u8 = 0b10;
+
+fn set_llwu_input(llwu: &LLWU, input: u8, mode: u8) {
+    let shift = (input % 4) * 2;
+    let update = |bits: u8| (bits & !(0b11 << shift)) | (mode << shift);
+    match input / 4 {
+        0 => llwu.pe1.modify(|r, w| unsafe { w.bits(update(r.bits())) }),
+        1 => llwu.pe2.modify(|r, w| unsafe { w.bits(update(r.bits())) }),
+        2 => llwu.pe3.modify(|r, w| unsafe { w.bits(update(r.bits())) }),
+        _ => llwu.pe4.modify(|r, w| unsafe { w.bits(update(r.bits())) }),
+    }
+}
+
+fn clear_llwu_flag(llwu: &LLWU, input: u8) {
+    if input < 8 {
+        llwu.f1.write(|w| unsafe { w.bits(1 << input) });
+    } else {
+        llwu.f2.write(|w| unsafe { w.bits(1 << (input - 8)) });
+    }
+}
+
+/// A `Serial` armed to wake the MCU on receive activity
+pub struct RxWakeup<UART, TXPIN, RXPIN> {
+    serial: Serial<UART, TXPIN, RXPIN>,
+    llwu_input: Option<u8>,
+}
+
+macro_rules! wakeup {
+    ($($UART:ident,)+) => {
+        $(
+            use crate::pac::$UART;
+
+            impl<TXPIN, RXPIN> Serial<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: RxPin<$UART>
+            {
+                /// Waits for the transmitter to finish and enables the RX
+                /// active edge interrupt
+                pub fn arm_rx_wakeup(self) -> RxWakeup<$UART, TXPIN, RXPIN> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    if uart.c2.read().te().bit() {
+                        while !uart.s1.read().tc().bit() {}
+                    }
+                    uart.s2.modify(|_, w| w.rxedgif().set_bit().lbkdif().clear_bit());
+                    uart.bdh.modify(|_, w| w.rxedgie().set_bit());
+                    RxWakeup { serial: self, llwu_input: None }
+                }
+            }
+
+            impl<TXPIN, RXPIN> RxWakeup<$UART, TXPIN, RXPIN>
+                where
+                    RXPIN: RxPin<$UART>
+            {
+                /// Routes the RX pin through the LLWU so the start bit also
+                /// wakes the MCU from LLS. The LLWU interrupt has to be
+                /// unmasked in the NVIC.
+                pub fn enable_llwu(&mut self, llwu: &LLWU)
+                    where
+                        RXPIN: LlwuRxPin<$UART>
+                {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    // The start bit is a rising edge on an inverted line
+                    let mode = if uart.s2.read().rxinv().bit() { LLWU_RISING } else { LLWU_FALLING };
+                    clear_llwu_flag(llwu, RXPIN::LLWU_INPUT);
+                    set_llwu_input(llwu, RXPIN::LLWU_INPUT, mode);
+                    self.llwu_input = Some(RXPIN::LLWU_INPUT);
+                }
+
+                /// Whether receive activity was seen since arming
+                pub fn is_woken(&self) -> bool {
+                    unsafe { (&*$UART::ptr()) }.s2.read().rxedgif().bit()
+                }
+
+                /// Disarms the wakeup sources, drops the partially received
+                /// wakeup character and returns the `Serial`
+                pub fn restore(self, llwu: &LLWU) -> Serial<$UART, TXPIN, RXPIN> {
+                    let uart = unsafe { (&*$UART::ptr())};
+                    if let Some(input) = self.llwu_input {
+                        set_llwu_input(llwu, input, LLWU_DISABLED);
+                        clear_llwu_flag(llwu, input);
+                    }
+                    uart.bdh.modify(|_, w| w.rxedgie().clear_bit());
+                    uart.s2.modify(|_, w| w.rxedgif().set_bit().lbkdif().clear_bit());
+                    // Reading S1 then D clears the receive and error flags
+                    let _ = uart.s1.read();
+                    let _ = uart.d.read();
+                    self.serial
+                }
+            }
+        )+
+    }
+}
+
+wakeup! {
+    UART0,
+    UART1,
+    UART2,
+    UART3,
+    UART4,
+    UART5,
+}
@@ -0,0 +1,273 @@
+//! Watchdog timer (WDOG)
+//!
+//! The WDOG runs out of reset with a timeout of about a second, unless it
+//! is disabled before `main` with the `disable-watchdog` feature.
+//! `WdogConfig` is applied when the watchdog is started, which is possible
+//! for as long as updates are allowed:
+//!
+//! ```ignore
+//! let mut wdog = Wdog::new(p.WDOG, WdogConfig::default().window(10.ms()), &clocks);
+//! wdog.start(100.ms());
+//! loop {
+//!     // ...
+//!     wdog.feed();
+//! }
+//! ```
+
+use core::ptr;
+use cortex_m::interrupt;
+use embedded_hal::watchdog::{Watchdog, WatchdogDisable, WatchdogEnable};
+use crate::clocks::{Clocks, LPO};
+use crate::pac::{wdog::RegisterBlock, WDOG};
+use crate::time::{Hertz, MilliSeconds};
+
+const UNLOCK_KEY1: u16 = 0xC520;
+const UNLOCK_KEY2: u16 = 0xD928;
+const REFRESH_KEY1: u16 = 0xA602;
+const REFRESH_KEY2: u16 = 0xB480;
+
+const STCTRLH_WDOGEN: u16 = 1 << 0;
+const STCTRLH_ALLOWUPDATE: u16 = 1 << 4;
+
+/// Shortest timeout accepted by the WDOG, in watchdog clock cycles
+const MIN_TIMEOUT: u32 = 4;
+
+/// Clock the watchdog counts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockSource {
+    /// The 1 kHz low-power oscillator, which keeps running in stop modes
+    Lpo,
+    /// The bus clock
+    Bus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TestMode {
+    /// Normal operation, test mode is disabled until the next reset
+    Off,
+    /// Quick test, the timeout counts the whole 32 bit timer at once
+    Quick,
+    /// Byte test, only the timer byte 0 to 3 counts
+    Byte(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WdogConfig {
+    clock: ClockSource,
+    window: Option<MilliSeconds>,
+    test_mode: TestMode,
+    interrupt: bool,
+    run_in_debug: bool,
+    run_in_wait: bool,
+    run_in_stop: bool,
+    allow_update: bool,
+}
+
+impl Default for WdogConfig {
+    fn default() -> Self {
+        WdogConfig {
+            clock: ClockSource::Lpo,
+            window: None,
+            test_mode: TestMode::Off,
+            interrupt: false,
+            run_in_debug: false,
+            run_in_wait: true,
+            run_in_stop: true,
+            allow_update: true,
+        }
+    }
+}
+
+impl WdogConfig {
+    pub fn clock_source(mut self, clock: ClockSource) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Windowed mode, refreshing earlier than `window` after the last
+    /// refresh resets the MCU. A window not shorter than the timeout is
+    /// cut to one watchdog clock below it.
+    pub fn window(mut self, window: MilliSeconds) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn test_mode(mut self, test_mode: TestMode) -> Self {
+        self.test_mode = test_mode;
+        self
+    }
+
+    /// Raises the WDOG interrupt before the reset, which follows 256 bus
+    /// clocks later
+    pub fn interrupt(mut self, enabled: bool) -> Self {
+        self.interrupt = enabled;
+        self
+    }
+
+    pub fn run_in_debug(mut self, enabled: bool) -> Self {
+        self.run_in_debug = enabled;
+        self
+    }
+
+    pub fn run_in_wait(mut self, enabled: bool) -> Self {
+        self.run_in_wait = enabled;
+        self
+    }
+
+    pub fn run_in_stop(mut self, enabled: bool) -> Self {
+        self.run_in_stop = enabled;
+        self
+    }
+
+    /// Locks the configuration until the next reset once started, `disable`
+    /// then leaves the watchdog running
+    pub fn lock(mut self) -> Self {
+        self.allow_update = false;
+        self
+    }
+}
+
+pub struct Wdog {
+    wdog: WDOG,
+    config: WdogConfig,
+    /// Frequency of the configured clock source
+    clock: Hertz,
+}
+
+/// Writes the unlock sequence, the registers then have to be updated
+/// within 256 bus clocks with interrupts masked
+///
+/// The second key has to follow the first within 20 bus clocks, even in an
+/// unoptimized build, so both are stored directly rather than through
+/// `write` closures.
+#[inline(always)]
+fn unlock(wdog: &RegisterBlock) {
+    let unlock = &wdog.unlock as *const _ as *mut u16;
+    unsafe {
+        ptr::write_volatile(unlock, UNLOCK_KEY1);
+        ptr::write_volatile(unlock, UNLOCK_KEY2);
+    }
+}
+
+/// Clears WDOGEN, leaving updates allowed. STCTRLH is read before unlocking
+/// so only a store follows the unlock sequence.
+#[inline(always)]
+fn unlock_and_disable(wdog: &RegisterBlock) {
+    let stctrlh = wdog.stctrlh.read().bits() & !STCTRLH_WDOGEN | STCTRLH_ALLOWUPDATE;
+    unlock(wdog);
+    unsafe { ptr::write_volatile(&wdog.stctrlh as *const _ as *mut u16, stctrlh) };
+}
+
+impl Wdog {
+    pub fn new(wdog: WDOG, config: WdogConfig, clocks: &Clocks) -> Self {
+        let clock = match config.clock {
+            ClockSource::Lpo => LPO,
+            ClockSource::Bus => clocks.bus(),
+        };
+        Wdog { wdog, config, clock }
+    }
+
+    fn ticks(&self, duration: MilliSeconds) -> u32 {
+        let ticks = duration.0 as u64 * self.clock.0 as u64 / 1_000;
+        if ticks > u32::MAX as u64 { u32::MAX } else { ticks as u32 }
+    }
+
+    /// Whether the configuration is locked until the next reset, so the
+    /// watchdog can neither be started again nor disabled
+    pub fn is_locked(&self) -> bool {
+        !self.wdog.stctrlh.read().allowupdate().bit()
+    }
+
+    /// Whether the interrupt preceding a watchdog reset is pending
+    pub fn is_interrupt_pending(&self) -> bool {
+        self.wdog.stctrll.read().intflg().bit()
+    }
+
+    /// Number of watchdog resets since the last power-on reset
+    pub fn reset_count(&self) -> u16 {
+        self.wdog.rstcnt.read().bits()
+    }
+
+    /// Clears the reset count
+    pub fn clear_reset_count(&mut self) {
+        self.wdog.rstcnt.write(|w| unsafe { w.bits(0xFFFF) });
+    }
+
+    pub fn free(self) -> WDOG {
+        self.wdog
+    }
+}
+
+impl WatchdogEnable for Wdog {
+    type Time = MilliSeconds;
+
+    fn start<T>(&mut self, period: T)
+        where
+            T: Into<MilliSeconds>
+    {
+        let timeout = self.ticks(period.into()).max(MIN_TIMEOUT);
+        let window = self.config.window.map(|window| self.ticks(window).min(timeout - 1)).unwrap_or(0);
+        let config = self.config;
+        let (test, byte) = match config.test_mode {
+            TestMode::Off => (false, 0),
+            TestMode::Quick => (true, 0),
+            TestMode::Byte(byte) => (true, byte & 0b11),
+        };
+        let wdog = &self.wdog;
+        interrupt::free(|_| {
+            unlock(wdog);
+            wdog.tovalh.write(|w| unsafe { w.bits((timeout >> 16) as u16) });
+            wdog.tovall.write(|w| unsafe { w.bits(timeout as u16) });
+            wdog.winh.write(|w| unsafe { w.bits((window >> 16) as u16) });
+            wdog.winl.write(|w| unsafe { w.bits(window as u16) });
+            wdog.presc.write(|w| unsafe { w.prescval().bits(0) });
+            wdog.stctrlh.write(|w| unsafe {
+                w.wdogen().set_bit()
+                    .clksrc().bit(config.clock == ClockSource::Bus)
+                    .irqrsten().bit(config.interrupt)
+                    .winen().bit(config.window.is_some())
+                    .allowupdate().bit(config.allow_update)
+                    .dbgen().bit(config.run_in_debug)
+                    .stopen().bit(config.run_in_stop)
+                    .waiten().bit(config.run_in_wait)
+                    .testwdog().bit(test)
+                    .testsel().bit(matches!(config.test_mode, TestMode::Byte(_)))
+                    .bytesel().bits(byte)
+                    .distestwdog().bit(!test)
+            });
+        })
+    }
+}
+
+impl Watchdog for Wdog {
+    fn feed(&mut self) {
+        let wdog = &self.wdog;
+        // Same 20 bus clock limit between the keys as for unlocking
+        let refresh = &wdog.refresh as *const _ as *mut u16;
+        interrupt::free(|_| unsafe {
+            ptr::write_volatile(refresh, REFRESH_KEY1);
+            ptr::write_volatile(refresh, REFRESH_KEY2);
+        })
+    }
+}
+
+impl WatchdogDisable for Wdog {
+    /// Does nothing once started with a locked configuration, see
+    /// `is_locked`
+    fn disable(&mut self) {
+        if self.is_locked() {
+            return;
+        }
+        let wdog = &self.wdog;
+        interrupt::free(|_| unlock_and_disable(wdog))
+    }
+}
+
+/// Disables the WDOG before RAM is initialized, so a long `.data` or `.bss`
+/// initialization cannot trigger it
+#[cfg(feature = "disable-watchdog")]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn __pre_init() {
+    unlock_and_disable(&*WDOG::ptr());
+}